@@ -1,23 +1,250 @@
+use bincode::Options;
+use indexmap::IndexSet;
+use lazy_static::lazy_static;
 use regex::{Regex, Captures};
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
 use std::collections::{HashSet, HashMap};
+use std::cmp::{Ordering, Reverse};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BinaryHeap;
+use std::error::Error;
+use std::fmt;
 use std::fmt::Debug;
-use std::iter;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::ops::Deref;
+use std::sync::OnceLock;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+// regex/capture stages applied in order to a document's text before it is split into terms.
+pub type Stage = (Regex, fn(cap: &Captures) -> String);
+pub type Pipeline = Vec<Stage>;
+
+// settings.pos_weight of the term in a column, 1 without one.
+fn pos_factor(settings: &Settings, dictionary: &IndexSet<String>, column: usize) -> f64 {
+    match (settings.pos_weight, dictionary.get_index(column)) {
+        (Some(pos_weight), Some(term)) => pos_weight(term),
+        _ => 1f64,
+    }
+}
+
+// pairs stored regex sources with their captures again, one capture per stage.
+fn compile_pipeline(sources: &[String], captures: &[fn(cap: &Captures) -> String]) -> Result<Pipeline, ModelError> {
+    if sources.len() != captures.len() {
+        return Err(ModelError::PipelineMismatch { expected: sources.len(), found: captures.len() });
+    }
+    sources.iter().zip(captures.iter())
+        .map(|(source, capture)| {
+            Regex::new(source).map(|regex| (regex, *capture)).map_err(|e| ModelError::Regex(e.to_string()))
+        })
+        .collect::<Result<Pipeline, ModelError>>()
+}
+
+lazy_static! {
+    static ref HYPHENATED: Regex = Regex::new(r"\w+(?:-\w+)+").unwrap();
+    static ref APOSTROPHE: Regex = Regex::new(r"(\w)['’](\w)").unwrap();
+}
+
+// smart notation document.query triples (tf, df, normalization): l = 1 + log tf, n = raw tf (first
+// letter) or no idf (second), t = idf, c = cosine. cosine is always applied by sim, LtcLtc is the default.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SmartScheme {
+    LtcLtc,
+    LncLtc,
+    LncLnc,
+    NtcNtc,
+}
+
+impl SmartScheme {
+    // (log tf, idf) of the document or the query triple.
+    fn components(&self, query: bool) -> (bool, bool) {
+        match (self, query) {
+            (SmartScheme::LtcLtc, _) => (true, true),
+            (SmartScheme::LncLtc, false) => (true, false),
+            (SmartScheme::LncLtc, true) => (true, true),
+            (SmartScheme::LncLnc, _) => (true, false),
+            (SmartScheme::NtcNtc, _) => (false, true),
+        }
+    }
+
+    // weight of a term occurring tf times in a document, or in the query when query is set.
+    pub fn weight(&self, tf: f64, idf: f64, log_base: f64, query: bool) -> f64 {
+        if tf == 0f64 { return 0f64; }
+
+        let (log_tf, use_idf) = self.components(query);
+        let tf = if log_tf { 1f64 + tf.log(log_base) } else { tf };
+        if use_idf { tf * idf } else { tf }
+    }
+}
+
+// what happens to hyphenated words like "state-of-the-art" before the pipeline runs.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum HyphenMode {
+    // leave the text to the pipeline.
+    Keep,
+    // "state of the art"
+    Split,
+    // "stateoftheart"
+    Join,
+    // "stateoftheart state of the art"
+    Both,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+    // drop every term that is not made up solely of (unicode) letters.
+    pub alpha_only: bool,
+    // lowercase terms left out of documents and queries.
+    pub stopwords: HashSet<String>,
+    // keep stopwords in the index (dictionary and frequencies) and only strip them from queries.
+    pub index_stopwords: bool,
+    // base of every logarithm in the tf and idf weighting, for documents and queries alike.
+    pub log_base: f64,
+    // hashing trick: terms are hashed (fnv-1a) into this many fixed columns instead of growing a dictionary,
+    // so vector_length never changes. colliding terms share a column, and the dictionary stays empty.
+    pub hashing_features: Option<usize>,
+    // scale search scores by the share of distinct query terms a document contains (lucene's coord).
+    pub coordination_factor: bool,
+    // collapse search results with identical get_data(), keeping the best scored one.
+    pub dedup: bool,
+    // leave document_weights empty and compute (then cache) a document's weights the first time it is scored.
+    // the mode is taken when the weights are computed, so on a built model it takes effect on the next
+    // calculate_document_weights.
+    pub lazy_weights: bool,
+    // normalize terms with unicode case folding (see Model::case_fold) instead of plain lowercasing.
+    pub case_folding: bool,
+    // keep documents scoring 0 (sharing nothing with the query) at the tail of search results.
+    pub include_non_matching: bool,
+    // index character n-grams of every term instead of the terms themselves, for fuzzy matching.
+    pub char_ngram: Option<usize>,
+    // groups of (preprocessed) terms that match each other at query time, e.g. {"js", "javascript"}.
+    pub synonyms: Vec<HashSet<String>>,
+    // weight multiplier for the synonyms a query term is expanded to.
+    pub synonym_weight: f64,
+    pub hyphens: HyphenMode,
+    // "don't" -> "dont" before the pipeline runs.
+    pub strip_apostrophes: bool,
+    // keep every document's tokens in order (as columns) in Model::positions.
+    pub store_positions: bool,
+    // queries keep only their first max_query_terms terms (after preprocessing, so n-grams with char_ngram)
+    // and the rest is ignored.
+    pub max_query_terms: Option<usize>,
+    // query terms found in more than this share of the documents get no weight. 1 keeps every term.
+    pub query_term_df_cutoff: f64,
+    // weight documents and queries by term frequency alone, every idf being 1.
+    pub use_idf: bool,
+    // search divides a document's score by its length in terms to this power, 0 disables it.
+    pub length_penalty: f64,
+    // min-max scale search scores over the returned results, the best becoming 1 and the worst 0.
+    pub normalize_scores: bool,
+    // index (and query) every pair of adjacent terms as a "first second" term next to the unigrams, with the
+    // query weight of matched bigrams multiplied by the boost.
+    pub bigram_boost: Option<f64>,
+    pub smart: SmartScheme,
+    // documents indexed from now on only count the terms already in the vocabulary, the rest are ignored
+    // and vector_length stays the same.
+    pub frozen_vocabulary: bool,
+    // search and its variants return at most this many results.
+    pub default_limit: Option<usize>,
+    // applied to every term after stopword removal (stopwords are matched unstemmed), e.g. Model::light_stem.
+    // functions are not serialized, a loaded model has to have its stemmer set again.
+    #[serde(skip)]
+    pub stemmer: Option<fn(&str) -> String>,
+    // multiplier for a (preprocessed) term's document weights, e.g. from a part of speech tagger ranking nouns
+    // above determiners. needs the dictionary, so has no effect with hashing_features. not serialized either.
+    // on a built model use Model::set_pos_weight, the stored weights are only updated by a recompute.
+    #[serde(skip)]
+    pub pos_weight: Option<fn(&str) -> f64>,
+    // count every query term once, so repeating a word in the query doesn't boost it.
+    pub binary_query: bool,
+    // lsh index behind search_ann: ann_tables hash tables of ann_bits (at most 64) hyperplanes each, drawn
+    // from ann_seed.
+    pub ann_tables: usize,
+    pub ann_bits: usize,
+    pub ann_seed: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            alpha_only: false,
+            stopwords: HashSet::new(),
+            index_stopwords: false,
+            log_base: 10f64,
+            hashing_features: None,
+            coordination_factor: false,
+            dedup: false,
+            lazy_weights: false,
+            case_folding: false,
+            include_non_matching: false,
+            char_ngram: None,
+            synonyms: vec![],
+            synonym_weight: 1f64,
+            hyphens: HyphenMode::Keep,
+            strip_apostrophes: false,
+            store_positions: false,
+            max_query_terms: None,
+            query_term_df_cutoff: 1f64,
+            use_idf: true,
+            length_penalty: 0f64,
+            normalize_scores: false,
+            bigram_boost: None,
+            smart: SmartScheme::LtcLtc,
+            frozen_vocabulary: false,
+            default_limit: None,
+            stemmer: None,
+            pos_weight: None,
+            binary_query: false,
+            ann_tables: 8,
+            ann_bits: 8,
+            ann_seed: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+}
 
 pub struct Model<T> {
     pub vector_length: usize,
     pub document_weights: Vec<Vec<f64>>,
     pub term_frequencies: Vec<Vec<usize>>,
     pub document_frequency: Vec<u64>,
-    pub dictionary: HashSet<String>,
-    pub index: HashMap<String, usize>,
+    // total occurrences of each term over all documents.
+    pub collection_frequency: Vec<u64>,
+    // settings, pipeline, vocabulary and (as of the last weight calculation) idf.
+    pub vectorizer: Vectorizer,
     pub documents: Vec<T>,
-    pub capture: fn(cap: &Captures) -> String,
-    pub processing_regex: Regex,
     pub queued_for_indexing: Vec<T>,
+    // (document frequency per term, number of documents) of a background corpus to take idf from.
+    pub background_idf: Option<(HashMap<String, u64>, usize)>,
+    // names of the document fields seen while indexing.
+    pub fields: HashSet<String>,
+    // column of every token of a document in order, repeats included, without bigram or field:term columns.
+    // only kept with settings.store_positions.
+    pub positions: Vec<Vec<usize>>,
+    // query independent quality of every document in [0, 1], see set_static_rank.
+    static_rank: Vec<f64>,
+    // replaces calc_idf when set, see set_idf_fn. not serialized, load_binary leaves it unset.
+    idf_fn: Option<fn(df: u64, n: usize) -> f64>,
+    // settings.lazy_weights as of the last calculate_document_weights, which of the two holds the weights.
+    weights_are_lazy: bool,
+    lazy_weights: Vec<OnceLock<Vec<f64>>>,
+    // built on first use and dropped whenever the weights are recalculated. being OnceLocks, every search
+    // takes &self only and a model shared through an Arc can be searched from several threads at once.
+    norms: OnceLock<Vec<f64>>,
+    inverted_index: OnceLock<Vec<Vec<usize>>>,
+    lsh: OnceLock<Vec<Lsh>>,
 }
 
 pub trait Document {
+    // an empty string, or one the pipeline strips down to nothing, gives no terms at all. such documents
+    // are indexed as all-zero rows and score 0 against every query.
     fn get_data(&self) -> String;
+
+    // named fields (e.g. title, body) that can be searched on their own with field:term queries.
+    fn get_fields(&self) -> Vec<(String, String)> {
+        vec![]
+    }
 }
 
 impl Document for String {
@@ -26,6 +253,266 @@ impl Document for String {
     }
 }
 
+// a log or event record. every value is indexed both as plain terms and under its key, so "level:error"
+// matches records whose level is error, while "error" matches it in any field.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyValueDocument {
+    pub pairs: Vec<(String, String)>,
+}
+
+impl KeyValueDocument {
+    pub fn new(pairs: &[(&str, &str)]) -> Self {
+        Self { pairs: pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect() }
+    }
+}
+
+impl Document for KeyValueDocument {
+    fn get_data(&self) -> String {
+        self.pairs.iter().map(|(_, value)| value.as_str()).collect::<Vec<_>>().join(" ")
+    }
+
+    fn get_fields(&self) -> Vec<(String, String)> {
+        self.pairs.clone()
+    }
+}
+
+// (score, document index), ordered by score with the lower index winning ties.
+#[derive(Debug, PartialEq)]
+struct Ranked(f64, usize);
+
+impl Eq for Ranked {}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.partial_cmp(&other.0).unwrap_or(Ordering::Equal).then_with(|| other.1.cmp(&self.1))
+    }
+}
+
+// comparisons of scores for sort_by that put NaN (e.g. from a custom idf_fn) after every number, where
+// partial_cmp().unwrap() would panic.
+fn ascending(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b).unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+}
+
+fn descending(a: f64, b: f64) -> Ordering {
+    b.partial_cmp(&a).unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+}
+
+// the best k of several (e.g. per shard) result lists, each already sorted by descending score.
+// equal scores keep the order of the lists they came from.
+pub fn merge_top_k(result_lists: &[Vec<(usize, f64)>], k: usize) -> Vec<(usize, f64)> {
+    // one entry per list, holding the score of its next unmerged result.
+    let mut heads = BinaryHeap::new();
+    let mut next = vec![0; result_lists.len()];
+    for (list, results) in result_lists.iter().enumerate() {
+        if let Some((_, score)) = results.first() {
+            heads.push(Ranked(*score, list));
+        }
+    }
+
+    let mut merged = vec![];
+    while merged.len() < k {
+        let list = match heads.pop() {
+            Some(Ranked(_, list)) => list,
+            None => break,
+        };
+        merged.push(result_lists[list][next[list]]);
+        next[list] += 1;
+        if let Some((_, score)) = result_lists[list].get(next[list]) {
+            heads.push(Ranked(*score, list));
+        }
+    }
+    merged
+}
+
+// one random hyperplane lsh table over the document weights. bit b of a signature is set when the vector
+// lies on the positive side of hyperplane b, so vectors at a small angle tend to land in the same bucket.
+struct Lsh {
+    hyperplanes: Vec<Vec<f64>>,
+    buckets: HashMap<u64, Vec<usize>>,
+}
+
+impl Lsh {
+    fn new(rows: &[&[f64]], dimensions: usize, bits: usize, state: &mut u64) -> Self {
+        let hyperplanes = (0..bits.min(64))
+            .map(|_| (0..dimensions).map(|_| Lsh::gaussian(state)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        let mut lsh = Lsh { hyperplanes, buckets: HashMap::new() };
+        for (i, row) in rows.iter().enumerate() {
+            let signature = Lsh::signature(&lsh.projections(row));
+            lsh.buckets.entry(signature).or_default().push(i);
+        }
+        lsh
+    }
+
+    fn xorshift(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    // box-muller over two uniforms in (0, 1].
+    fn gaussian(state: &mut u64) -> f64 {
+        let mut uniform = || ((Lsh::xorshift(state) >> 11) as f64 + 1f64) / (1u64 << 53) as f64;
+        let (u, v) = (uniform(), uniform());
+        (-2f64 * u.ln()).sqrt() * (2f64 * std::f64::consts::PI * v).cos()
+    }
+
+    fn projections(&self, v: &[f64]) -> Vec<f64> {
+        self.hyperplanes.iter()
+            .map(|hyperplane| hyperplane.iter().zip(v.iter()).map(|(a, b)| a * b).sum::<f64>())
+            .collect::<Vec<_>>()
+    }
+
+    fn signature(projections: &[f64]) -> u64 {
+        projections.iter().enumerate()
+            .filter(|(_, projection)| **projection > 0f64)
+            .fold(0, |signature, (b, _)| signature | 1 << b)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModelError {
+    DimensionMismatch { expected: usize, found: usize },
+    // the entry at index is NaN or infinite.
+    NonFinite { index: usize },
+    // a stored model needs one capture per pipeline stage.
+    PipelineMismatch { expected: usize, found: usize },
+    Regex(String),
+    Serialization(String),
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::DimensionMismatch { expected, found } => {
+                write!(f, "vector has length {}, the model expects {}", found, expected)
+            }
+            ModelError::NonFinite { index } => write!(f, "vector entry {} is not a finite number", index),
+            ModelError::PipelineMismatch { expected, found } => {
+                write!(f, "got {} captures for a pipeline of {} stages", found, expected)
+            }
+            ModelError::Regex(message) => write!(f, "invalid pipeline regex: {}", message),
+            ModelError::Serialization(message) => write!(f, "could not (de)serialize the model: {}", message),
+        }
+    }
+}
+
+impl Error for ModelError {}
+
+// what save_binary writes: the index state without weights (recomputed on load) and the vectorizer, whose
+// pipeline is kept as regex sources since its captures are plain functions that have to be supplied again.
+#[derive(Serialize)]
+struct StoredModelRef<'a, T> {
+    vectorizer: &'a Vectorizer,
+    vector_length: usize,
+    term_frequencies: &'a Vec<Vec<usize>>,
+    document_frequency: &'a Vec<u64>,
+    collection_frequency: &'a Vec<u64>,
+    documents: &'a Vec<T>,
+    background_idf: &'a Option<(HashMap<String, u64>, usize)>,
+    fields: &'a HashSet<String>,
+    positions: &'a Vec<Vec<usize>>,
+    static_rank: &'a Vec<f64>,
+}
+
+#[derive(Deserialize)]
+struct StoredModel<T> {
+    vectorizer: Vectorizer,
+    vector_length: usize,
+    term_frequencies: Vec<Vec<usize>>,
+    document_frequency: Vec<u64>,
+    collection_frequency: Vec<u64>,
+    documents: Vec<T>,
+    background_idf: Option<(HashMap<String, u64>, usize)>,
+    fields: HashSet<String>,
+    positions: Vec<Vec<usize>>,
+    static_rank: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchTimings {
+    pub preprocessing: Duration,
+    pub query_weights: Duration,
+    pub scoring: Duration,
+    pub sorting: Duration,
+}
+
+// how many of a query's distinct terms a document has to contain, see search_min_should_match.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MinShouldMatch {
+    Count(usize),
+    // share of the distinct query terms, rounded down.
+    Fraction(f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DistanceMetric {
+    Cosine,
+    Euclidean,
+    DotProduct,
+}
+
+// why a document scores what it does against a query, see diagnose.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZeroReason {
+    OutOfRange,
+    // none of the (preprocessed) query terms is in the vocabulary.
+    NoKnownQueryTerms,
+    // the document's weights are all zero: it has no terms, or only ones every document has.
+    EmptyDocument,
+    // the document contains none of the query terms.
+    NoSharedTerms,
+    // the terms it shares with the query carry no weight (e.g. idf 0).
+    ZeroWeightTerms,
+    // not zero at all.
+    Scored(f64),
+}
+
+// must: every term has to be present, should: ranked on (and required when there is no must),
+// must_not: documents containing any of these are dropped.
+#[derive(Debug, Clone, Default)]
+pub struct BooleanQuery {
+    pub must: Vec<String>,
+    pub should: Vec<String>,
+    pub must_not: Vec<String>,
+}
+
+// sparse term-term similarity used by soft cosine, pairs are stored once with the terms in sorted order.
+// a term is always fully similar to itself, unknown pairs are 0.
+#[derive(Debug, Clone, Default)]
+pub struct TermSimilarity {
+    pub pairs: HashMap<(String, String), f64>,
+}
+
+impl TermSimilarity {
+    pub fn new() -> Self {
+        Self { pairs: HashMap::new() }
+    }
+
+    fn key(a: &str, b: &str) -> (String, String) {
+        if a <= b { (a.to_string(), b.to_string()) } else { (b.to_string(), a.to_string()) }
+    }
+
+    pub fn insert(&mut self, a: &str, b: &str, similarity: f64) {
+        if a == b { return; }
+        self.pairs.insert(TermSimilarity::key(a, b), similarity);
+    }
+
+    pub fn get(&self, a: &str, b: &str) -> f64 {
+        if a == b { return 1f64; }
+        *self.pairs.get(&TermSimilarity::key(a, b)).unwrap_or(&0f64)
+    }
+}
+
 
 impl<T> Model<T> where T: Document + Debug + Clone {
     pub fn index() {}
@@ -34,205 +521,3434 @@ impl<T> Model<T> where T: Document + Debug + Clone {
         self.queued_for_indexing.extend(docs);
     }
 
-    pub fn preprocess(doc: &T, func: &fn(cap: &Captures) -> String, processing_regex: &Regex) -> Vec<String> {
-        String::from(processing_regex.replace_all(&doc.get_data(), func).clone())
-            .split(" ").clone()
-            .map(|data| String::from(data).to_lowercase())
-            .filter(|data| data != "" && data != " ")
+    // full unicode case folding on top of to_lowercase: characters whose folded form differs from their
+    // lowercase form (ß -> ss, final sigma, ligatures, the dot of turkish İ) are expanded as well.
+    pub fn case_fold(term: &str) -> String {
+        let mut folded = String::with_capacity(term.len());
+        let mut previous = ' ';
+        for c in term.to_lowercase().chars() {
+            match c {
+                'ß' => folded.push_str("ss"),
+                'ς' => folded.push('σ'),
+                'ſ' => folded.push('s'),
+                'ŉ' => folded.push_str("ʼn"),
+                'ﬀ' => folded.push_str("ff"),
+                'ﬁ' => folded.push_str("fi"),
+                'ﬂ' => folded.push_str("fl"),
+                'ﬃ' => folded.push_str("ffi"),
+                'ﬄ' => folded.push_str("ffl"),
+                'ﬅ' | 'ﬆ' => folded.push_str("st"),
+                // İ lowercases to i + combining dot above, fold it to a plain i.
+                '\u{307}' if previous == 'i' => {}
+                c => folded.push(c),
+            }
+            previous = c;
+        }
+        folded
+    }
+
+    pub fn preprocess(doc: &T, pipeline: &[Stage], settings: &Settings) -> Vec<String> {
+        Model::<T>::preprocess_text(&doc.get_data(), pipeline, settings, false)
+    }
+
+    // settings.strip_apostrophes and settings.hyphens, applied to the raw text before the pipeline.
+    pub fn normalize_punctuation(text: &str, settings: &Settings) -> String {
+        let text = if settings.strip_apostrophes {
+            String::from(APOSTROPHE.replace_all(text, "$1$2"))
+        } else {
+            String::from(text)
+        };
+
+        if settings.hyphens == HyphenMode::Keep { return text; }
+        String::from(HYPHENATED.replace_all(&text, |cap: &Captures| {
+            let word = &cap[0];
+            match settings.hyphens {
+                HyphenMode::Split => word.replace('-', " "),
+                HyphenMode::Join => word.replace('-', ""),
+                _ => format!("{} {}", word.replace('-', ""), word.replace('-', " ")),
+            }
+        }))
+    }
+
+    // the words, then their char_ngram grams (or the words themselves), then their bigrams with bigram_boost.
+    pub fn preprocess_text(text: &str, pipeline: &[Stage], settings: &Settings, query: bool) -> Vec<String> {
+        let words = Model::<T>::words(text, pipeline, settings, query);
+        let mut terms = Model::<T>::unigrams(&words, settings);
+        terms.extend(Model::<T>::bigrams(&words, settings));
+        terms
+    }
+
+    // queries always drop stopwords, documents keep them when settings.index_stopwords is set.
+    pub fn words(text: &str, pipeline: &[Stage], settings: &Settings, query: bool) -> Vec<String> {
+        pipeline.iter()
+            .fold(Model::<T>::normalize_punctuation(text, settings), |data, (regex, func)| String::from(regex.replace_all(&data, func)))
+            .split(' ')
+            // captures can leave other whitespace (tabs, newlines) around a term, "rust\t" must not become its own term.
+            .map(str::trim)
+            .map(|data| if settings.case_folding { Model::<T>::case_fold(data) } else { data.to_lowercase() })
+            // splitting "" (or runs of spaces) yields empty strings, drop them rather than index empty terms.
+            .filter(|data| !data.is_empty())
+            .filter(|data| !settings.alpha_only || data.chars().all(char::is_alphabetic))
+            .filter(|data| (settings.index_stopwords && !query) || !settings.stopwords.contains(data))
+            .map(|data| match settings.stemmer { Some(stem) => stem(&data), None => data })
+            .filter(|data| !data.is_empty())
             .collect::<Vec<_>>()
     }
 
+    // the single-token terms of the words, in order: their char_ngram grams if set, else the words.
+    pub fn unigrams(words: &[String], settings: &Settings) -> Vec<String> {
+        match settings.char_ngram {
+            Some(n) if n > 0 => words.iter().flat_map(|word| Model::<T>::char_ngrams(word, n)).collect(),
+            _ => words.to_vec(),
+        }
+    }
+
+    pub fn bigrams(words: &[String], settings: &Settings) -> Vec<String> {
+        match settings.bigram_boost {
+            Some(_) => words.windows(2).map(|pair| format!("{} {}", pair[0], pair[1])).collect(),
+            None => vec![],
+        }
+    }
+
+    // what a single raw term is indexed as after the pipeline, case normalization, stopwords and stemming,
+    // None if nothing is left. terms the pipeline splits (or char_ngram grams) are joined by spaces.
+    pub fn normalize_term(&self, raw: &str) -> Option<String> {
+        let terms = Model::<T>::preprocess_text(raw, &self.vectorizer.pipeline, &self.vectorizer.settings, true);
+        if terms.is_empty() { return None; }
+        Some(terms.join(" "))
+    }
+
+    // a light english suffix stripper: -ing, -ed, -es and -s come off when at least three characters remain,
+    // undoubling a final consonant left behind ("running" -> "run").
+    pub fn light_stem(term: &str) -> String {
+        let chars = term.chars().collect::<Vec<_>>();
+        let suffix = ["ing", "ed", "es", "s"].iter()
+            .find(|suffix| term.ends_with(**suffix) && chars.len() >= suffix.len() + 3);
+        let mut stem = match suffix {
+            Some(suffix) if !term.ends_with("ss") => chars[..chars.len() - suffix.len()].to_vec(),
+            _ => return String::from(term),
+        };
+
+        let n = stem.len();
+        if n >= 4 && stem[n - 1] == stem[n - 2] && !"aeiouls".contains(stem[n - 1]) {
+            stem.pop();
+        }
+        stem.iter().collect::<String>()
+    }
+
+    // the character n-grams of a term, terms shorter than n are kept whole.
+    pub fn char_ngrams(term: &str, n: usize) -> Vec<String> {
+        let chars = term.chars().collect::<Vec<_>>();
+        if chars.len() <= n { return vec![String::from(term)]; }
+
+        chars.windows(n).map(|gram| gram.iter().collect::<String>()).collect::<Vec<_>>()
+    }
+
+    pub fn fnv1a(term: &str) -> u64 {
+        term.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+    }
+
+    fn hashing_features(&self) -> Option<usize> {
+        self.vectorizer.settings.hashing_features.filter(|n| *n > 0)
+    }
+
+    // the column a term maps to, if any.
+    pub fn column(&self, term: &str) -> Option<usize> {
+        self.vectorizer.column(term)
+    }
+
+    pub fn scoped_term(field: &str, term: &str) -> String {
+        format!("{}:{}", field, term)
+    }
+
+    fn preprocess_query(&self, query: String) -> Vec<String> {
+        let mut preprocessed = self.preprocess_query_terms(query);
+        if let Some(max) = self.vectorizer.settings.max_query_terms {
+            preprocessed.truncate(max);
+        }
+        preprocessed
+    }
+
+    fn preprocess_query_terms(&self, query: String) -> Vec<String> {
+        let preprocess = |text: &str| Model::<T>::preprocess_text(text, &self.vectorizer.pipeline, &self.vectorizer.settings, true);
+
+        if self.fields.is_empty() {
+            return preprocess(&query);
+        }
+
+        // field:term only matches the term inside that field, anything else matches everywhere. the rest of
+        // the query is preprocessed as one text like a document, each scoped term goes back between the
+        // unigrams of the words around it so max_query_terms still keeps the first terms of the query.
+        let words = |rest: &[&str]| Model::<T>::words(&rest.join(" "), &self.vectorizer.pipeline, &self.vectorizer.settings, true);
+        let mut scoped = vec![];
+        let mut rest = vec![];
+        for word in query.split_whitespace() {
+            match word.split_once(':') {
+                Some((field, term)) if self.fields.contains(field) => {
+                    let before = Model::<T>::unigrams(&words(&rest), &self.vectorizer.settings).len();
+                    scoped.extend(preprocess(term).iter().map(|term| (before, Model::<T>::scoped_term(field, term))));
+                }
+                _ => rest.push(word),
+            }
+        }
+        if scoped.is_empty() {
+            return preprocess(&query);
+        }
+
+        let rest = words(&rest);
+        let unigrams = Model::<T>::unigrams(&rest, &self.vectorizer.settings);
+        let mut terms = vec![];
+        let mut taken = 0;
+        for (before, term) in scoped {
+            let before = before.clamp(taken, unigrams.len());
+            terms.extend(unigrams[taken..before].iter().cloned());
+            taken = before;
+            terms.push(term);
+        }
+        terms.extend(unigrams[taken..].iter().cloned());
+        terms.extend(Model::<T>::bigrams(&rest, &self.vectorizer.settings));
+        terms
+    }
+
     pub fn build_query_weights(&self, query_vec: &[i32]) -> Vec<f64> {
         query_vec.iter().enumerate().map(|(i, term)| {
-            if *term == 0 { return 0f64; }
-            Model::<T>::calc_query_tf(term) * Model::<T>::calc_query_idf(
-                self.documents.len(),
-                self.document_frequency[i],
-            )
+            // a column no document uses (possible with hashing_features) carries no weight.
+            if *term == 0 || self.document_frequency[i] == 0 || self.above_df_cutoff(i) { return 0f64; }
+            self.vectorizer.settings.smart.weight(*term as f64, self.idf(i), self.vectorizer.settings.log_base, true)
         }).collect::<Vec<f64>>()
     }
 
-    pub fn search(&self, query: String) -> Vec<(T, f64)> {
-        let preprocessed = Model::preprocess(
-            &query,
-            &self.capture,
-            &self.processing_regex.clone(),
-        );
+    fn above_df_cutoff(&self, column: usize) -> bool {
+        let ratio = self.document_frequency[column] as f64 / self.documents.len() as f64;
+        ratio > self.vectorizer.settings.query_term_df_cutoff
+    }
+
+    // term counts and weights of a preprocessed query, with synonyms expanded.
+    fn weigh_query(&self, preprocessed: &[String]) -> (Vec<i32>, Vec<f64>) {
+        let query_vec = self.count_query_terms(preprocessed);
+        let mut query_weight = self.build_query_weights(&query_vec);
+        self.expand_synonyms(preprocessed, &query_vec, &mut query_weight);
+        if let Some(boost) = self.vectorizer.settings.bigram_boost {
+            preprocessed.iter()
+                .filter(|term| term.contains(' '))
+                .filter_map(|term| self.column(term))
+                .collect::<HashSet<_>>().iter()
+                .for_each(|column| query_weight[*column] *= boost);
+        }
+        (query_vec, query_weight)
+    }
 
-        let mut query_vec = vec![0; self.vector_length];
+    // members of a synonym group the query did not use get the weight they would have had, scaled by
+    // settings.synonym_weight.
+    fn expand_synonyms(&self, preprocessed: &[String], query_vec: &[i32], query_weight: &mut [f64]) {
+        for group in self.vectorizer.settings.synonyms.iter() {
+            let mut count = preprocessed.iter().filter(|term| group.contains(*term)).count() as i32;
+            if count == 0 { continue; }
+            if self.vectorizer.settings.binary_query { count = 1; }
+
+            for column in group.iter().filter_map(|term| self.column(term)) {
+                if query_vec[column] > 0 || self.document_frequency[column] == 0 || self.above_df_cutoff(column) {
+                    continue;
+                }
+                query_weight[column] = self.vectorizer.settings.smart.weight(count as f64, self.idf(column), self.vectorizer.settings.log_base, true)
+                    * self.vectorizer.settings.synonym_weight;
+            }
+        }
+    }
 
+    fn count_columns(&self, preprocessed: &[String]) -> Vec<i32> {
+        let mut counts = vec![0; self.vector_length];
         preprocessed.iter().for_each(|item| {
-            if self.index.contains_key(item) {
-                query_vec[*self.index.get(item).unwrap()] += 1
+            if let Some(column) = self.column(item) {
+                counts[column] += 1
             }
         });
+        counts
+    }
 
+    // count_columns, with every count capped at 1 under settings.binary_query.
+    fn count_query_terms(&self, preprocessed: &[String]) -> Vec<i32> {
+        let mut query_vec = self.count_columns(preprocessed);
+        if self.vectorizer.settings.binary_query {
+            query_vec.iter_mut().for_each(|count| *count = (*count).min(1));
+        }
+        query_vec
+    }
 
-        //calc query weight
-        let query_weight = self.build_query_weights(&query_vec);
+    pub fn query_vector(&self, query: String) -> Vec<f64> {
+        let preprocessed = self.preprocess_query(query);
+        self.weigh_query(&preprocessed).1
+    }
 
-        //calculate sim
-        let mut vec = self.document_weights.iter().enumerate().map(|(i, doc)| {
-            let res = (Model::<T>::sim(&query_weight, doc), i);
-            res
-        }).collect::<Vec<(f64, usize)>>();
+    // the per-column term counts search weighs, before tf-idf.
+    pub fn query_counts(&self, query: String) -> Vec<usize> {
+        let preprocessed = self.preprocess_query(query);
+        self.count_query_terms(&preprocessed).iter().map(|count| *count as usize).collect::<Vec<_>>()
+    }
 
-        vec.sort_by(|f, b| b.0.partial_cmp(&f.0).unwrap());
+    // query_vector keyed by the query's own terms, unmatched terms are left out.
+    pub fn query_term_weights(&self, query: String) -> HashMap<String, f64> {
+        let preprocessed = self.preprocess_query(query);
+        let query_weight = self.weigh_query(&preprocessed).1;
 
-        vec.iter().map(|item| { (self.documents[item.1].clone(), item.0) }).collect::<Vec<_>>()
+        preprocessed.into_iter()
+            .filter_map(|term| {
+                let column = self.column(&term)?;
+                if query_weight[column] == 0f64 { return None; }
+                Some((term, query_weight[column]))
+            })
+            .collect::<HashMap<_, _>>()
     }
 
-    pub fn calc_idf(term_df: u64, total_items: usize) -> f64 {
-        (total_items as f64 / term_df as f64).log10()
-    }
+    // pre-retrieval difficulty predictor: the average idf of the query terms found in the vocabulary (0 if
+    // none are). high values mean specific terms and usually a clear-cut query, low ones a vague query of
+    // common terms.
+    pub fn query_difficulty(&self, query: String) -> f64 {
+        let idfs = self.preprocess_query(query).iter()
+            .filter_map(|term| self.column(term))
+            .filter(|column| self.document_frequency[*column] > 0)
+            .map(|column| self.idf(column))
+            .collect::<Vec<_>>();
 
-    pub fn calc_tf_idf(term_frequency: usize, idf: f64) -> f64 {
-        if term_frequency == 0 { return 0f64; }
-        (1f64 + (term_frequency as f64).log10()) * idf
+        if idfs.is_empty() { return 0f64; }
+        idfs.iter().sum::<f64>() / idfs.len() as f64
     }
 
-    pub fn calc_query_tf(term: &i32) -> f64 {
-        1.0 + (*term as f64).log10()
-    }
+    // share of the (preprocessed) query terms found in the dictionary, 0 means nothing can match.
+    pub fn query_coverage(&self, query: String) -> f64 {
+        let preprocessed = self.preprocess_query(query);
+        if preprocessed.is_empty() { return 0f64; }
 
-    pub fn calc_query_idf(num_docs: usize, doc_freq: u64) -> f64 {
-        (num_docs as f64 / doc_freq as f64).log10()
+        let known = preprocessed.iter().filter(|term| self.column(term).is_some()).count();
+        known as f64 / preprocessed.len() as f64
     }
 
-    pub fn euclidean_len(v: &[f64]) -> f64 {
-        v.iter().fold(0.0, |acc, elm| acc + elm.powi(2)).sqrt()
+    // |Q ∩ D| / min(|Q|, |D|) over the distinct terms of the query and the document. 0 when either has
+    // no terms, None for out of range indices.
+    pub fn overlap_coefficient(&self, query: String, doc_index: usize) -> Option<f64> {
+        let document = self.term_frequencies.get(doc_index)?;
+        let query_terms = self.preprocess_query(query).into_iter().collect::<HashSet<_>>();
+        let document_terms = document.iter().filter(|tf| **tf > 0).count();
+
+        let smaller = query_terms.len().min(document_terms);
+        if smaller == 0 { return Some(0f64); }
+
+        let shared = query_terms.iter()
+            .filter_map(|term| self.column(term))
+            .collect::<HashSet<_>>().iter()
+            .filter(|column| document[**column] > 0)
+            .count();
+        Some(shared as f64 / smaller as f64)
     }
 
-    pub fn sim(query: &[f64], doc: &[f64]) -> f64 {
+    // matched / total distinct query terms for a document.
+    pub fn coordination(&self, query_vec: &[i32], doc_index: usize) -> f64 {
+        let query_terms = query_vec.iter().enumerate().filter(|(_, count)| **count > 0).collect::<Vec<_>>();
+        if query_terms.is_empty() { return 0f64; }
 
+        let matched = query_terms.iter()
+            .filter(|(i, _)| self.term_frequencies[doc_index][*i] > 0)
+            .count();
+        matched as f64 / query_terms.len() as f64
+    }
 
+    // 1 / length^settings.length_penalty, 1 for empty documents.
+    fn length_factor(&self, doc_index: usize) -> f64 {
+        if self.vectorizer.settings.length_penalty == 0f64 { return 1f64; }
 
-        let q_len = Model::<T>::euclidean_len(&query);
-        let d_len = Model::<T>::euclidean_len(&doc);
+        let length = self.term_frequencies[doc_index].iter().sum::<usize>();
+        if length == 0 { return 1f64; }
+        1f64 / (length as f64).powf(self.vectorizer.settings.length_penalty)
+    }
 
-        query.iter().enumerate().map(|(i, term)| (term / q_len) * (doc[i] / d_len))
-            .sum::<f64>()
+    // results all sharing one score get 1.
+    fn min_max_normalize(ranked: &mut [(f64, usize)]) {
+        let max = ranked.iter().map(|(score, _)| *score).fold(f64::NEG_INFINITY, f64::max);
+        let min = ranked.iter().map(|(score, _)| *score).fold(f64::INFINITY, f64::min);
+        ranked.iter_mut().for_each(|(score, _)| {
+            *score = if max > min { (*score - min) / (max - min) } else { 1f64 };
+        });
     }
 
-    pub fn update_index(&mut self) { // TODO refactor and extract processing into separate functions for reuse in construct and update_index.
-        if self.queued_for_indexing.is_empty() {
-            println!("No documents queued");
-            return;
+    fn drop_non_matching(&self, ranked: &mut Vec<(f64, usize)>) {
+        if !self.vectorizer.settings.include_non_matching {
+            ranked.retain(|(score, _)| *score > 0f64);
         }
+    }
+
+    pub fn search(&self, query: String) -> Vec<(T, f64)> {
+        let preprocessed = self.preprocess_query(query);
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("search", query_terms = preprocessed.len()).entered();
+
+        //calc query weight
+        let (query_vec, query_weight) = self.weigh_query(&preprocessed);
+        self.with_documents(&self.rank(&query_vec, &query_weight, |_| Some(1f64)))
+    }
 
-        let capture = &self.capture;
-        let reg = &self.processing_regex;
-        let num_docs = self.queued_for_indexing.len();
+    // search without the documents in exclude, leaving the index as it is.
+    pub fn search_excluding(&self, query: String, exclude: &HashSet<usize>) -> Vec<(T, f64)> {
+        let (query_vec, query_weight) = self.weigh_query(&self.preprocess_query(query));
+        self.with_documents(&self.rank(&query_vec, &query_weight, |i| (!exclude.contains(&i)).then_some(1f64)))
+    }
 
-        self.documents.extend(self.queued_for_indexing.clone());
-        let doc_count = self.queued_for_indexing.len();
-        let processed = self.queued_for_indexing
-            .drain(..)
-            .map(|doc| Model::<T>::preprocess(&doc, capture, reg))
+    // search with the query terms whose idf is below min_idf left out.
+    pub fn search_min_idf(&self, query: String, min_idf: f64) -> Vec<(T, f64)> {
+        let preprocessed = self.preprocess_query(query).into_iter()
+            .filter(|term| self.column(term).is_some_and(|column| self.idf(column) >= min_idf))
             .collect::<Vec<_>>();
 
-        let tmp_dict = processed.clone()
-            .drain(..)
-            .flatten()
-            .collect::<HashSet<_>>();
+        let (query_vec, query_weight) = self.weigh_query(&preprocessed);
+        self.with_documents(&self.rank(&query_vec, &query_weight, |_| Some(1f64)))
+    }
 
+    // ranks against weight_a * a + weight_b * b, each query vector normalized to unit length first so the
+    // weights alone decide how much either query counts.
+    pub fn search_combined(&self, query_a: String, weight_a: f64, query_b: String, weight_b: f64) -> Vec<(T, f64)> {
+        let (counts_a, query_weight_a) = self.weigh_query(&self.preprocess_query(query_a));
+        let (counts_b, query_weight_b) = self.weigh_query(&self.preprocess_query(query_b));
+        let unit = |v: &[f64]| {
+            let length = Model::<T>::euclidean_len(v);
+            if length == 0f64 { 0f64 } else { 1f64 / length }
+        };
+        let (scale_a, scale_b) = (weight_a * unit(&query_weight_a), weight_b * unit(&query_weight_b));
 
-        // TODO swap dict, index to use indexmap: https://docs.rs/indexmap/1.6.2/indexmap/
-        self.dictionary.extend(tmp_dict);
+        let query_vec = counts_a.iter().zip(counts_b.iter()).map(|(a, b)| a + b).collect::<Vec<_>>();
+        let query_weight = query_weight_a.iter().zip(query_weight_b.iter())
+            .map(|(a, b)| scale_a * a + scale_b * b)
+            .collect::<Vec<_>>();
+        self.with_documents(&self.rank(&query_vec, &query_weight, |_| Some(1f64)))
+    }
 
-        let vector_length = self.dictionary.len();
+    // the ranking behind every search variant: score_documents over the whole corpus, then order_results.
+    fn rank<F: Fn(usize) -> Option<f64>>(&self, query_vec: &[i32], query_weight: &[f64], adjust: F) -> Vec<(f64, usize)> {
+        #[cfg(feature = "tracing")]
+        let start = Instant::now();
 
-        let mut document_frequency = vec![0; vector_length];
-        let mut term_frequencies: Vec<Vec<usize>> = iter::repeat_with(|| vec![0; vector_length])
-            .take(num_docs)
-            .collect();
+        //calculate sim
+        let mut vec = self.score_documents(query_vec, query_weight, 0..self.documents.len(), adjust);
+        #[cfg(feature = "tracing")]
+        let scored = vec.len();
 
+        self.order_results(&mut vec, |_, _| Ordering::Equal);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(scored, results = vec.len(), duration = ?start.elapsed(), "search done");
+        vec
+    }
 
-        self.index = self.dictionary
-            .clone()
-            .iter()
-            .enumerate()
-            .map(|(i, item)| { (item.clone(), i) })
-            .collect::<HashMap<_, _>>();
+    // cosine of each document against the query, scaled by length_factor, static rank, coordination (when
+    // set) and adjust(document) of the variant, e.g. a decay. documents adjust gives None are left out.
+    fn score_documents<I, F>(&self, query_vec: &[i32], query_weight: &[f64], documents: I, adjust: F) -> Vec<(f64, usize)>
+    where I: Iterator<Item = usize>, F: Fn(usize) -> Option<f64> {
+        let sparse_query = Model::<T>::sparse_query(query_weight);
 
-        for i in 0..doc_count{
-            for term in processed[i].iter(){
-                let term_index = self.index.get(term).unwrap().clone();
-                if term_frequencies[i][term_index] == 0{
-                    document_frequency[term_index] += 1;
-                }
-                term_frequencies[i][term_index] += 1;
+        documents.filter_map(|i| {
+            let mut score = self.sparse_sim(&sparse_query, i) * self.length_factor(i) * adjust(i)?;
+            score *= 1f64 + self.static_rank.get(i).copied().unwrap_or(0f64);
+            if self.vectorizer.settings.coordination_factor {
+                score *= self.coordination(query_vec, i);
             }
+            Some((score, i))
+        }).collect::<Vec<(f64, usize)>>()
+    }
+
+    // sorts scored documents best first, equal scores by tiebreak(a, b) and then index, and applies the
+    // result settings: include_non_matching, dedup, default_limit and normalize_scores.
+    fn order_results<F: Fn(usize, usize) -> Ordering>(&self, vec: &mut Vec<(f64, usize)>, tiebreak: F) {
+        vec.sort_by(|f, b| descending(f.0, b.0).then_with(|| tiebreak(f.1, b.1)));
+        self.drop_non_matching(vec);
+
+        if self.vectorizer.settings.dedup {
+            let mut seen = HashSet::new();
+            vec.retain(|item| seen.insert(Model::<T>::content_hash(&self.documents[item.1])));
+        }
+        if let Some(limit) = self.vectorizer.settings.default_limit {
+            vec.truncate(limit);
+        }
+        if self.vectorizer.settings.normalize_scores {
+            Model::<T>::min_max_normalize(vec);
         }
+    }
+
+    fn with_documents(&self, ranked: &[(f64, usize)]) -> Vec<(T, f64)> {
+        ranked.iter().map(|item| { (self.documents[item.1].clone(), item.0) }).collect::<Vec<_>>()
+    }
 
-        self.document_frequency.iter().enumerate().map(|(i,df)| document_frequency[i]+df).collect::<Vec<_>>();
+    // search, keeping only documents with at least min of the distinct query terms.
+    pub fn search_min_should_match(&self, query: String, min: MinShouldMatch) -> Vec<(T, f64)> {
+        let preprocessed = self.preprocess_query(query);
+        let (query_vec, query_weight) = self.weigh_query(&preprocessed);
 
-        // reconstruct the model.
-        //extend self vars with the above.
+        let distinct = preprocessed.iter().collect::<HashSet<_>>().len();
+        let required = match min {
+            MinShouldMatch::Count(count) => count,
+            MinShouldMatch::Fraction(fraction) => (fraction * distinct as f64).floor() as usize,
+        };
+        let columns = query_vec.iter().enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
 
+        let ranked = self.rank(&query_vec, &query_weight, |i| {
+            let matched = columns.iter().filter(|column| self.term_frequencies[i][**column] > 0).count();
+            (matched >= required).then_some(1f64)
+        });
+        self.with_documents(&ranked)
     }
 
-    pub fn calculate_document_weights(&mut self) {
-        self.document_weights = self.term_frequencies.iter().map(|document| {
-            document.iter().enumerate().map(|(i, tf)| {
-                let idf = Model::<T>::calc_idf(
-                    self.document_frequency[i],
-                    self.documents.len(),
-                );
-                Model::<T>::calc_tf_idf(*tf, idf)
-            }).collect::<Vec<_>>()
-        }).collect::<Vec<Vec<f64>>>();
+    // share of the pairs of distinct query columns occurring at most slop terms apart in the document.
+    pub fn proximity(&self, query_columns: &[usize], doc_index: usize, slop: usize) -> f64 {
+        let pairs = query_columns.len() * query_columns.len().saturating_sub(1) / 2;
+        let sequence = match self.positions.get(doc_index) {
+            Some(sequence) if pairs > 0 => sequence,
+            _ => return 0f64,
+        };
+
+        let occurrences = sequence.iter().enumerate()
+            .filter(|(_, column)| query_columns.contains(column))
+            .collect::<Vec<_>>();
+        let mut close = HashSet::new();
+        for (k, (position, column)) in occurrences.iter().enumerate() {
+            for (previous, other) in occurrences[..k].iter().rev() {
+                if position - previous > slop + 1 { break; }
+                if column != other { close.insert((*column.min(other), *column.max(other))); }
+            }
+        }
+        close.len() as f64 / pairs as f64
     }
 
-    pub fn construct(documents: Vec<T>, processing_capture: fn(cap: &Captures) -> String, processing_regex: Regex) -> Self {
-        let processed = documents.iter().map(|doc| {
-            Model::preprocess(doc, &processing_capture, &processing_regex.clone())
-        }).collect::<Vec<_>>();
+    // search with every score scaled by 1 + proximity, so documents with the query terms close together
+    // rank higher. needs settings.store_positions, without positions it is plain cosine.
+    pub fn search_proximity(&self, query: String, slop: usize) -> Vec<(T, f64)> {
+        let (query_vec, query_weight) = self.weigh_query(&self.preprocess_query(query));
+        let query_columns = query_vec.iter().enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
 
-        let dictionary = processed.clone().drain(..)
-            .flatten()
-            .collect::<HashSet<_>>();
+        let ranked = self.rank(&query_vec, &query_weight, |i| Some(1f64 + self.proximity(&query_columns, i, slop)));
+        self.with_documents(&ranked)
+    }
 
-        let num_docs = documents.len();
-        let vector_len = dictionary.len();
+    pub fn content_hash(doc: &T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        doc.get_data().hash(&mut hasher);
+        hasher.finish()
+    }
 
-        let mut document_frequency = vec![0; vector_len];
-        let mut term_frequencies: Vec<Vec<usize>> = iter::repeat_with(|| vec![0; vector_len])
-            .take(num_docs)
-            .collect();
+    // the first cause found of a document scoring 0 against the query (without the coordination factor).
+    pub fn diagnose(&self, query: String, doc_index: usize) -> ZeroReason {
+        if doc_index >= self.documents.len() { return ZeroReason::OutOfRange; }
 
-        let index = dictionary.iter().enumerate().map(
-            |(i, item)| {
-                (item.clone(), i)
-            }
-        ).collect::<HashMap<_, _>>();
+        let (query_vec, query_weight) = self.weigh_query(&self.preprocess_query(query));
+        if query_vec.iter().all(|count| *count == 0) { return ZeroReason::NoKnownQueryTerms; }
 
+        let weights = self.weights_of(doc_index);
+        if weights.iter().all(|weight| *weight == 0f64) { return ZeroReason::EmptyDocument; }
 
-        // built term frequency and document frequency
-        for i in 0..num_docs {
-            for term in processed[i].iter() {
-                let term_index = index.get(term).unwrap().clone();
-                if term_frequencies[i][term_index] == 0 {
-                    document_frequency[term_index] += 1;
-                }
-                term_frequencies[i][term_index] += 1;
-            }
+        let shared = query_vec.iter().enumerate()
+            .any(|(i, count)| *count > 0 && self.term_frequencies[doc_index][i] > 0);
+        if !shared { return ZeroReason::NoSharedTerms; }
+
+        match Model::<T>::sim(&query_weight, weights) {
+            score if score > 0f64 => ZeroReason::Scored(score),
+            _ => ZeroReason::ZeroWeightTerms,
         }
+    }
 
-        let mut model = Self {
-            vector_length: vector_len,
-            capture: processing_capture,
-            queued_for_indexing: vec![],
-            document_weights:vec![],
-            term_frequencies,
-            document_frequency,
-            dictionary,
-            index,
-            documents,
-            processing_regex,
-        };
+    // the score search gives one document (before normalize_scores), without scoring the rest. None for out
+    // of range indices.
+    pub fn score(&self, query: String, doc_index: usize) -> Option<f64> {
+        if doc_index >= self.documents.len() { return None; }
 
-        model.calculate_document_weights();
-        model
+        let (query_vec, query_weight) = self.weigh_query(&self.preprocess_query(query));
+        self.score_documents(&query_vec, &query_weight, std::iter::once(doc_index), |_| Some(1f64))
+            .first()
+            .map(|(score, _)| *score)
+    }
+
+    pub fn search_with_index(&self, query: String) -> Vec<(usize, T, f64)> {
+        let (query_vec, query_weight) = self.weigh_query(&self.preprocess_query(query));
+
+        self.rank(&query_vec, &query_weight, |_| Some(1f64)).iter()
+            .map(|item| { (item.1, self.documents[item.1].clone(), item.0) })
+            .collect::<Vec<_>>()
     }
-}
\ No newline at end of file
+
+    // like search, but documents with equal scores are ordered by the tiebreak key, ascending.
+    pub fn search_sorted_by<K: Ord, F: Fn(&T) -> K>(&self, query: String, tiebreak: F) -> Vec<(T, f64)> {
+        let (query_vec, query_weight) = self.weigh_query(&self.preprocess_query(query));
+
+        let mut vec = self.score_documents(&query_vec, &query_weight, 0..self.documents.len(), |_| Some(1f64));
+        self.order_results(&mut vec, |a, b| tiebreak(&self.documents[a]).cmp(&tiebreak(&self.documents[b])));
+        self.with_documents(&vec)
+    }
+
+    // like search, with each document's score multiplied by decay(document), e.g. to favour recent documents.
+    pub fn search_time_decayed<F: Fn(&T) -> f64>(&self, query: String, decay: F) -> Vec<(T, f64)> {
+        let (query_vec, query_weight) = self.weigh_query(&self.preprocess_query(query));
+        self.with_documents(&self.rank(&query_vec, &query_weight, |i| Some(decay(&self.documents[i]))))
+    }
+
+    // dirichlet smoothed query likelihood: sum over query terms of log((tf + mu * cf / |C|) / (|d| + mu)).
+    // scores are log probabilities (<= 0), higher is better. like search, documents containing none of the
+    // query terms are left out unless include_non_matching is set. none of the cosine settings (length_penalty,
+    // static rank, coordination_factor, dedup, default_limit, normalize_scores) apply.
+    pub fn search_query_likelihood(&self, query: String, mu: f64) -> Vec<(T, f64)> {
+        let query_vec = self.count_query_terms(&self.preprocess_query(query));
+        let collection_length = self.collection_frequency.iter().sum::<u64>() as f64;
+
+        let query_terms = query_vec.iter().enumerate()
+            .filter(|(i, count)| **count > 0 && self.collection_frequency[*i] > 0)
+            .map(|(i, count)| (i, *count as f64, self.collection_frequency[i] as f64 / collection_length))
+            .collect::<Vec<_>>();
+        if query_terms.is_empty() { return vec![]; }
+
+        let mut vec = self.term_frequencies.iter().enumerate()
+            .filter(|(_, document)| {
+                self.vectorizer.settings.include_non_matching || query_terms.iter().any(|(i, _, _)| document[*i] > 0)
+            })
+            .map(|(d, document)| {
+                let length = document.iter().sum::<usize>() as f64;
+                let score = query_terms.iter().map(|(i, count, p)| {
+                    count * ((document[*i] as f64 + mu * p) / (length + mu)).ln()
+                }).sum::<f64>();
+                (score, d)
+            }).collect::<Vec<(f64, usize)>>();
+
+        vec.sort_by(|f, b| descending(f.0, b.0));
+
+        vec.iter().map(|item| { (self.documents[item.1].clone(), item.0) }).collect::<Vec<_>>()
+    }
+
+    fn intersect(a: &[usize], b: &[usize]) -> Vec<usize> {
+        let (mut i, mut j) = (0, 0);
+        let mut both = vec![];
+        while i < a.len() && j < b.len() {
+            match a[i].cmp(&b[j]) {
+                Ordering::Less => i += 1,
+                Ordering::Greater => j += 1,
+                Ordering::Equal => {
+                    both.push(a[i]);
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        both
+    }
+
+    // the candidates come from intersecting the postings lists of the must terms (shortest first), or the
+    // union of the should terms' lists without any must. they are ranked like search against must + should.
+    pub fn search_boolean(&self, query: &BooleanQuery) -> Vec<(T, f64)> {
+        let terms = |clause: &[String]| {
+            clause.iter().flat_map(|term| self.preprocess_query(term.clone())).collect::<Vec<_>>()
+        };
+        let must = terms(&query.must);
+        let should = terms(&query.should);
+        let inverted_index = self.inverted_index();
+
+        let mut candidates = if !must.is_empty() {
+            let columns = must.iter().map(|term| self.column(term)).collect::<Option<Vec<_>>>();
+            let mut postings = match columns {
+                Some(columns) => columns.iter().map(|column| &inverted_index[*column]).collect::<Vec<_>>(),
+                None => return vec![],
+            };
+            postings.sort_by_key(|list| list.len());
+            postings[1..].iter().fold(postings[0].clone(), |acc, list| Model::<T>::intersect(&acc, list))
+        } else {
+            let mut union = should.iter()
+                .filter_map(|term| self.column(term))
+                .flat_map(|column| inverted_index[column].iter().copied())
+                .collect::<Vec<_>>();
+            union.sort_unstable();
+            union.dedup();
+            union
+        };
+
+        let excluded = terms(&query.must_not).iter()
+            .filter_map(|term| self.column(term))
+            .collect::<Vec<_>>();
+        candidates.retain(|i| excluded.iter().all(|column| self.term_frequencies[*i][*column] == 0));
+
+        let ranking = must.iter().chain(should.iter()).cloned().collect::<Vec<_>>();
+        let (query_vec, query_weight) = self.weigh_query(&ranking);
+
+        let mut vec = self.score_documents(&query_vec, &query_weight, candidates.into_iter(), |_| Some(1f64));
+        self.order_results(&mut vec, |_, _| Ordering::Equal);
+        self.with_documents(&vec)
+    }
+
+    // same ranking as search, with the time spent in each phase.
+    pub fn search_timed(&self, query: String) -> (Vec<(T, f64)>, SearchTimings) {
+        let start = Instant::now();
+        let preprocessed = self.preprocess_query(query);
+        let preprocessing = start.elapsed();
+
+        let start = Instant::now();
+        let (query_vec, query_weight) = self.weigh_query(&preprocessed);
+        let query_weights = start.elapsed();
+
+        let start = Instant::now();
+        let mut vec = self.score_documents(&query_vec, &query_weight, 0..self.documents.len(), |_| Some(1f64));
+        let scoring = start.elapsed();
+
+        let start = Instant::now();
+        self.order_results(&mut vec, |_, _| Ordering::Equal);
+        let sorting = start.elapsed();
+
+        (self.with_documents(&vec), SearchTimings { preprocessing, query_weights, scoring, sorting })
+    }
+
+    // maps texts into the corpus feature space (column order of the index), like a fitted tf-idf vectorizer.
+    pub fn transform(&self, texts: &[String]) -> Vec<Vec<f64>> {
+        self.vectorizer.transform(texts)
+    }
+
+    // cosine of two texts in the corpus feature space, terms outside the vocabulary are ignored.
+    pub fn text_similarity(&self, a: String, b: String) -> f64 {
+        Model::<T>::sim(&self.vectorizer.vector(&a), &self.vectorizer.vector(&b))
+    }
+
+    // the k documents closest to text weighed like a document, by plain cosine: search's score settings
+    // (length_penalty, static rank, coordination_factor) don't apply, its result settings
+    // (include_non_matching, dedup, default_limit, normalize_scores) do.
+    pub fn similar_to_text(&self, text: String, k: usize) -> Vec<(T, f64)> {
+        let text_weight = self.vectorizer.vector(&text);
+
+        let mut vec = self.weight_rows().enumerate().map(|(i, doc)| {
+            (Model::<T>::sim(&text_weight, doc), i)
+        }).collect::<Vec<(f64, usize)>>();
+
+        self.order_results(&mut vec, |_, _| Ordering::Equal);
+        vec.truncate(k);
+        self.with_documents(&vec)
+    }
+
+    // scores a preselected set of documents (e.g. from a first stage retriever) by plain cosine, without
+    // search's settings, skipping unknown indices.
+    pub fn rerank(&self, query: String, candidates: &[usize]) -> Vec<(usize, f64)> {
+        let query_weight = self.query_vector(query);
+
+        let mut ranked = candidates.iter()
+            .filter(|i| **i < self.documents.len())
+            .map(|i| (*i, Model::<T>::sim(&query_weight, self.weights_of(*i))))
+            .collect::<Vec<_>>();
+
+        ranked.sort_by(|f, b| descending(f.1, b.1));
+        ranked
+    }
+
+    // the k documents closest to query_vec under metric: highest similarity first for Cosine and DotProduct,
+    // smallest distance first for Euclidean. query_vec has to have vector_length finite entries.
+    pub fn nearest_by_vector(&self, query_vec: &[f64], k: usize, metric: DistanceMetric) -> Result<Vec<(usize, f64)>, ModelError> {
+        if query_vec.len() != self.vector_length {
+            return Err(ModelError::DimensionMismatch { expected: self.vector_length, found: query_vec.len() });
+        }
+        if let Some(index) = query_vec.iter().position(|weight| !weight.is_finite()) {
+            return Err(ModelError::NonFinite { index });
+        }
+
+        let mut ranked = self.weight_rows().enumerate()
+            .map(|(i, doc)| {
+                let score = match metric {
+                    DistanceMetric::Cosine => Model::<T>::sim(query_vec, doc),
+                    DistanceMetric::DotProduct => query_vec.iter().zip(doc.iter()).map(|(a, b)| a * b).sum::<f64>(),
+                    DistanceMetric::Euclidean => {
+                        query_vec.iter().zip(doc.iter()).map(|(a, b)| (a - b).powi(2)).sum::<f64>().sqrt()
+                    }
+                };
+                (i, score)
+            })
+            .collect::<Vec<_>>();
+
+        match metric {
+            DistanceMetric::Euclidean => ranked.sort_by(|f, b| ascending(f.1, b.1)),
+            _ => ranked.sort_by(|f, b| descending(f.1, b.1)),
+        }
+        ranked.truncate(k);
+        Ok(ranked)
+    }
+
+    // ranks documents against caller supplied term weights. terms are preprocessed like a query (a term
+    // splitting into several gives each the weight) and those outside the vocabulary are ignored.
+    pub fn search_sparse_query(&self, query: &HashMap<String, f64>) -> Vec<(T, f64)> {
+        let mut query_weight = vec![0f64; self.vector_length];
+        for (term, weight) in query.iter() {
+            self.preprocess_query(term.clone()).iter()
+                .filter_map(|term| self.column(term))
+                .for_each(|column| query_weight[column] += weight);
+        }
+        // every weighted term counts as one occurrence for coordination_factor.
+        let query_vec = query_weight.iter().map(|weight| (*weight != 0f64) as i32).collect::<Vec<_>>();
+        self.with_documents(&self.rank(&query_vec, &query_weight, |_| Some(1f64)))
+    }
+
+    // the ranking of search by document index only, without cloning any document.
+    pub fn ranked_indices(&self, query: String) -> Vec<(usize, f64)> {
+        let (query_vec, query_weight) = self.weigh_query(&self.preprocess_query(query));
+
+        self.rank(&query_vec, &query_weight, |_| Some(1f64)).iter()
+            .map(|(score, i)| (*i, *score))
+            .collect::<Vec<_>>()
+    }
+
+    pub fn search_top_k(&self, query: String, k: usize) -> Vec<(T, f64)> {
+        self.ranked_indices(query).iter()
+            .take(k)
+            .map(|(i, score)| (self.documents[*i].clone(), *score))
+            .collect::<Vec<_>>()
+    }
+
+    // approximate search_top_k: only the documents sharing the query's bucket in some lsh table are scored,
+    // plus, per table, those in the buckets reached by flipping each of the probes bits the query is closest
+    // to. much faster on large corpora, but relevant documents hashed elsewhere are missed; more probes or
+    // settings.ann_tables (or fewer ann_bits) raise recall at the cost of scoring more documents.
+    // scores are plain cosine, without search's settings. the index is built on first use.
+    pub fn search_ann(&self, query: String, k: usize, probes: usize) -> Vec<(T, f64)> {
+        let query_weight = self.query_vector(query);
+
+        let mut candidates = HashSet::new();
+        for table in self.lsh() {
+            let projections = table.projections(&query_weight);
+            let signature = Lsh::signature(&projections);
+            let mut bits = (0..projections.len()).collect::<Vec<_>>();
+            bits.sort_by(|f, b| ascending(projections[*f].abs(), projections[*b].abs()));
+
+            std::iter::once(signature)
+                .chain(bits.iter().take(probes).map(|b| signature ^ 1 << b))
+                .filter_map(|signature| table.buckets.get(&signature))
+                .for_each(|bucket| candidates.extend(bucket.iter().copied()));
+        }
+
+        let mut ranked = candidates.into_iter()
+            .map(|i| (i, Model::<T>::sim(&query_weight, self.weights_of(i))))
+            .filter(|(_, score)| *score > 0f64)
+            .collect::<Vec<_>>();
+
+        ranked.sort_by(|f, b| descending(f.1, b.1).then_with(|| f.0.cmp(&b.0)));
+        ranked.iter()
+            .take(k)
+            .map(|(i, score)| (self.documents[*i].clone(), *score))
+            .collect::<Vec<_>>()
+    }
+
+    // the highest idf term found in the top 10 results but not in the query, ties going to the earlier column.
+    pub fn suggest_refinement(&self, query: String) -> Option<String> {
+        let query_columns = self.preprocess_query(query.clone()).iter()
+            .filter_map(|term| self.column(term))
+            .collect::<HashSet<_>>();
+
+        let mut candidates = HashSet::new();
+        for (i, _) in self.ranked_indices(query).iter().take(10) {
+            self.term_frequencies[*i].iter().enumerate()
+                .filter(|(column, tf)| **tf > 0 && !query_columns.contains(column))
+                .for_each(|(column, _)| { candidates.insert(column); });
+        }
+
+        let mut candidates = candidates.into_iter()
+            .filter_map(|column| self.vectorizer.dictionary.get_index(column).map(|term| (column, term)))
+            .collect::<Vec<_>>();
+        candidates.sort_by(|f, b| descending(self.idf(f.0), self.idf(b.0)).then_with(|| f.0.cmp(&b.0)));
+        candidates.first().map(|(_, term)| (*term).clone())
+    }
+
+    // ranked_indices partitioned by group_by(document), every group keeps the ranking order.
+    pub fn search_grouped<K: Eq + Hash, F: Fn(&T) -> K>(&self, query: String, group_by: F) -> HashMap<K, Vec<(usize, f64)>> {
+        let mut groups: HashMap<K, Vec<(usize, f64)>> = HashMap::new();
+        for (i, score) in self.ranked_indices(query) {
+            groups.entry(group_by(&self.documents[i])).or_default().push((i, score));
+        }
+        groups
+    }
+
+    // the top k plain cosine scores >= min_score, search's settings are not applied since the bound below only
+    // holds for cosine. documents sharing no query term are never scored, and a document is only scored
+    // exactly when the best it could reach (the length of the normalized query over the columns it shares)
+    // can still get it into the result.
+    pub fn search_top_k_threshold(&self, query: String, k: usize, min_score: f64) -> Vec<(T, f64)> {
+        if k == 0 { return vec![]; }
+
+        let query_weight = self.query_vector(query);
+        let q_len = Model::<T>::euclidean_len(&query_weight);
+        if q_len == 0f64 { return vec![]; }
+
+        let query_columns = query_weight.iter().enumerate()
+            .filter(|(_, weight)| **weight != 0f64)
+            .map(|(i, weight)| (i, weight / q_len))
+            .collect::<Vec<_>>();
+
+        let mut heap: BinaryHeap<Reverse<Ranked>> = BinaryHeap::new();
+        for (i, document) in self.term_frequencies.iter().enumerate() {
+            let bound = query_columns.iter()
+                .filter(|(column, _)| document[*column] > 0)
+                .map(|(_, weight)| weight.powi(2))
+                .sum::<f64>()
+                .sqrt();
+            if bound == 0f64 || bound < min_score { continue; }
+            if heap.len() == k && bound <= heap.peek().unwrap().0.0 { continue; }
+
+            let score = Model::<T>::sim(&query_weight, self.weights_of(i));
+            if score <= 0f64 || score < min_score { continue; }
+
+            let ranked = Ranked(score, i);
+            if heap.len() < k {
+                heap.push(Reverse(ranked));
+            } else if ranked > heap.peek().unwrap().0 {
+                heap.pop();
+                heap.push(Reverse(ranked));
+            }
+        }
+
+        heap.into_sorted_vec().iter()
+            .map(|Reverse(Ranked(score, i))| (self.documents[*i].clone(), *score))
+            .collect::<Vec<_>>()
+    }
+
+    pub fn calc_idf(term_df: u64, total_items: usize, log_base: f64) -> f64 {
+        (total_items as f64 / term_df as f64).log(log_base)
+    }
+
+    // the ltc weights from before settings.smart, kept for callers that still use them.
+    pub fn calc_tf_idf(term_frequency: usize, idf: f64, log_base: f64) -> f64 {
+        SmartScheme::LtcLtc.weight(term_frequency as f64, idf, log_base, false)
+    }
+
+    pub fn calc_query_tf(term: &i32, log_base: f64) -> f64 {
+        SmartScheme::LtcLtc.weight(*term as f64, 1f64, log_base, true)
+    }
+
+    pub fn calc_query_idf(num_docs: usize, doc_freq: u64, log_base: f64) -> f64 {
+        Model::<T>::calc_idf(doc_freq, num_docs, log_base)
+    }
+
+    pub fn euclidean_len(v: &[f64]) -> f64 {
+        v.iter().fold(0.0, |acc, elm| acc + elm.powi(2)).sqrt()
+    }
+
+    pub fn sim(query: &[f64], doc: &[f64]) -> f64 {
+
+
+
+        let q_len = Model::<T>::euclidean_len(query);
+        let d_len = Model::<T>::euclidean_len(doc);
+
+        // an all-zero vector (no known query terms, empty document or empty vocabulary) has no direction.
+        if q_len == 0f64 || d_len == 0f64 { return 0f64; }
+
+        query.iter().enumerate().map(|(i, term)| (term / q_len) * (doc[i] / d_len))
+            .sum::<f64>()
+    }
+
+    // the nonzero columns of a query, divided by its length. empty for an all-zero query.
+    pub fn sparse_query(query: &[f64]) -> Vec<(usize, f64)> {
+        let q_len = Model::<T>::euclidean_len(query);
+        if q_len == 0f64 { return vec![]; }
+
+        query.iter().enumerate()
+            .filter(|(_, term)| **term != 0f64)
+            .map(|(i, term)| (i, term / q_len))
+            .collect::<Vec<_>>()
+    }
+
+    // sim against a document for a sparse_query, exactly the same score but only visiting the query's
+    // columns and taking the document's length from the cached norms. a query of a few terms against a wide
+    // vocabulary costs a handful of multiplications per document instead of vector_length.
+    pub fn sparse_sim(&self, query: &[(usize, f64)], doc_index: usize) -> f64 {
+        let d_len = self.document_norms()[doc_index];
+        if query.is_empty() || d_len == 0f64 { return 0f64; }
+
+        let doc = self.weights_of(doc_index);
+        query.iter().map(|(i, term)| term * (doc[*i] / d_len)).sum::<f64>()
+    }
+
+    // mean of the given documents' length normalized weight vectors, so every document counts the same in
+    // cosine terms. out of range indices are skipped, all zeros for none.
+    pub fn centroid(&self, doc_indices: &[usize]) -> Vec<f64> {
+        let mut centroid = vec![0f64; self.vector_length];
+        let rows = doc_indices.iter().filter(|i| **i < self.documents.len()).collect::<Vec<_>>();
+        for i in rows.iter() {
+            let norm = self.document_norms()[**i];
+            if norm == 0f64 { continue; }
+            centroid.iter_mut().zip(self.weights_of(**i).iter()).for_each(|(sum, weight)| *sum += weight / norm);
+        }
+        if !rows.is_empty() {
+            centroid.iter_mut().for_each(|sum| *sum /= rows.len() as f64);
+        }
+        centroid
+    }
+
+    // the k terms weighing the most more in set_a's centroid than in set_b's, with that difference.
+    pub fn distinguishing_terms(&self, set_a: &[usize], set_b: &[usize], k: usize) -> Vec<(String, f64)> {
+        let (centroid_a, centroid_b) = (self.centroid(set_a), self.centroid(set_b));
+
+        let mut terms = self.vectorizer.dictionary.iter().enumerate()
+            .map(|(i, term)| (term, centroid_a[i] - centroid_b[i]))
+            .filter(|(_, difference)| *difference > 0f64)
+            .collect::<Vec<_>>();
+
+        terms.sort_by(|f, b| descending(f.1, b.1));
+        terms.iter().take(k).map(|(term, difference)| ((*term).clone(), *difference)).collect::<Vec<_>>()
+    }
+
+    // cosine of every document to the centroid of the whole corpus, high for typical documents.
+    pub fn representativeness(&self) -> Vec<f64> {
+        let centroid = self.centroid(&(0..self.documents.len()).collect::<Vec<_>>());
+        self.weight_rows().map(|doc| Model::<T>::sim(&centroid, doc)).collect::<Vec<_>>()
+    }
+
+    // cosine between every pair of documents: symmetric, with 1 on the diagonal for documents with any
+    // weight and 0 for empty ones. every pair is compared over their nonzero columns, so this costs
+    // O(N² · terms per document) time and N² memory, meant for small corpora (clustering, plots).
+    pub fn similarity_matrix(&self) -> Vec<Vec<f64>> {
+        let rows = self.weight_rows().map(Model::<T>::sparse_query).collect::<Vec<_>>();
+        let n = rows.len();
+
+        let mut matrix = vec![vec![0f64; n]; n];
+        for i in 0..n {
+            for j in i..n {
+                let (mut a, mut b) = (rows[i].iter().peekable(), rows[j].iter().peekable());
+                let mut dot = 0f64;
+                while let (Some((x, wx)), Some((y, wy))) = (a.peek(), b.peek()) {
+                    match x.cmp(y) {
+                        Ordering::Less => { a.next(); }
+                        Ordering::Greater => { b.next(); }
+                        Ordering::Equal => {
+                            dot += wx * wy;
+                            a.next();
+                            b.next();
+                        }
+                    }
+                }
+                matrix[i][j] = dot;
+                matrix[j][i] = dot;
+            }
+        }
+        matrix
+    }
+
+    // terms with their document frequency, ties broken alphabetically.
+    fn terms_by_document_frequency(&self, descending: bool) -> Vec<(String, u64)> {
+        let mut terms = self.vectorizer.dictionary.iter().enumerate()
+            .map(|(i, term)| (term.clone(), self.document_frequency[i]))
+            .collect::<Vec<_>>();
+
+        terms.sort_by(|f, b| {
+            let by_df = if descending { b.1.cmp(&f.1) } else { f.1.cmp(&b.1) };
+            by_df.then_with(|| f.0.cmp(&b.0))
+        });
+        terms
+    }
+
+    // document frequency of each term after preprocessing, None for terms outside the vocabulary.
+    pub fn document_frequencies(&self, terms: &[&str]) -> Vec<Option<u64>> {
+        terms.iter().map(|term| {
+            Model::<T>::preprocess_text(term, &self.vectorizer.pipeline, &self.vectorizer.settings, false)
+                .first()
+                .and_then(|term| self.column(term))
+                .map(|column| self.document_frequency[column])
+        }).collect::<Vec<_>>()
+    }
+
+    pub fn collection_frequency_of(&self, term: &str) -> Option<u64> {
+        self.column(term).map(|column| self.collection_frequency[column])
+    }
+
+    // every dictionary term with its document frequency, in column order.
+    pub fn vocabulary_frequencies(&self) -> impl Iterator<Item = (&String, u64)> + '_ {
+        self.vectorizer.dictionary.iter().enumerate().map(move |(i, term)| (term, self.document_frequency[i]))
+    }
+
+    pub fn sorted_vocabulary(&self) -> Vec<&String> {
+        let mut terms = self.vectorizer.dictionary.iter().collect::<Vec<_>>();
+        terms.sort();
+        terms
+    }
+
+    pub fn rarest_terms(&self, n: usize) -> Vec<(String, u64)> {
+        self.terms_by_document_frequency(false).into_iter().take(n).collect()
+    }
+
+    pub fn common_terms(&self, n: usize) -> Vec<(String, u64)> {
+        self.terms_by_document_frequency(true).into_iter().take(n).collect()
+    }
+
+    // the k documents the term weighs the most in, ties broken by document index.
+    pub fn documents_by_term_weight(&self, term: &str, k: usize) -> Vec<(usize, f64)> {
+        let column = match self.column(term) {
+            Some(column) => column,
+            None => return vec![],
+        };
+
+        let mut documents = (0..self.documents.len())
+            .map(|i| (i, self.weights_of(i)[column]))
+            .filter(|(_, weight)| *weight > 0f64)
+            .collect::<Vec<_>>();
+
+        documents.sort_by(|f, b| descending(f.1, b.1).then_with(|| f.0.cmp(&b.0)));
+        documents.truncate(k);
+        documents
+    }
+
+    // the terms no other document contains, in column order. empty for out of range indices.
+    pub fn unique_terms(&self, doc_index: usize) -> Vec<String> {
+        let document = match self.term_frequencies.get(doc_index) {
+            Some(document) => document,
+            None => return vec![],
+        };
+
+        self.vectorizer.dictionary.iter().enumerate()
+            .filter(|(i, _)| document[*i] > 0 && self.document_frequency[*i] == 1)
+            .map(|(_, term)| term.clone())
+            .collect::<Vec<_>>()
+    }
+
+    // shannon entropy in bits of the document's relative term frequencies. 0 for a document repeating a
+    // single term (or having none), None for out of range indices.
+    pub fn document_entropy(&self, doc_index: usize) -> Option<f64> {
+        let document = self.term_frequencies.get(doc_index)?;
+        let total = document.iter().sum::<usize>() as f64;
+
+        let entropy = document.iter()
+            .filter(|tf| **tf > 0)
+            .map(|tf| {
+                let p = *tf as f64 / total;
+                p * (1f64 / p).log2()
+            })
+            .sum::<f64>();
+        Some(entropy)
+    }
+
+    // soft cosine: x·y plus s(i,j) * x_i * y_j for every similar pair of columns.
+    pub fn soft_dot(x: &[f64], y: &[f64], pairs: &[(usize, usize, f64)]) -> f64 {
+        let dot = x.iter().zip(y.iter()).map(|(a, b)| a * b).sum::<f64>();
+        dot + pairs.iter()
+            .map(|(i, j, s)| s * (x[*i] * y[*j] + x[*j] * y[*i]))
+            .sum::<f64>()
+    }
+
+    pub fn soft_sim(query: &[f64], doc: &[f64], pairs: &[(usize, usize, f64)]) -> f64 {
+        let q_len = Model::<T>::soft_dot(query, query, pairs).sqrt();
+        let d_len = Model::<T>::soft_dot(doc, doc, pairs).sqrt();
+        if q_len == 0f64 || d_len == 0f64 { return 0f64; }
+
+        Model::<T>::soft_dot(query, doc, pairs) / (q_len * d_len)
+    }
+
+    // ranks by soft cosine alone: search's score settings (length_penalty, static rank, coordination_factor)
+    // don't apply, its result settings (include_non_matching, dedup, default_limit, normalize_scores) do.
+    pub fn search_soft_cosine(&self, query: String, term_sim: &TermSimilarity) -> Vec<(T, f64)> {
+        // only pairs where both terms are in the vocabulary matter.
+        let pairs = term_sim.pairs.iter()
+            .filter_map(|((a, b), s)| {
+                match (self.column(a), self.column(b)) {
+                    (Some(i), Some(j)) => Some((i, j, *s)),
+                    _ => None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let query_weight = self.query_vector(query);
+
+        let mut vec = self.weight_rows().enumerate().map(|(i, doc)| {
+            (Model::<T>::soft_sim(&query_weight, doc, &pairs), i)
+        }).collect::<Vec<(f64, usize)>>();
+
+        self.order_results(&mut vec, |_, _| Ordering::Equal);
+        self.with_documents(&vec)
+    }
+
+    // derives term similarity from co-occurrence: co(a, b) / sqrt(df(a) * df(b)), keeping pairs at or above min_similarity.
+    pub fn cooccurrence_similarity(&self, min_similarity: f64) -> TermSimilarity {
+        let terms = self.vectorizer.dictionary.iter().enumerate().collect::<HashMap<_, _>>();
+        let mut counts: HashMap<(usize, usize), u64> = HashMap::new();
+
+        for document in self.term_frequencies.iter() {
+            let present = document.iter().enumerate()
+                .filter(|(_, tf)| **tf > 0)
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>();
+            for (n, i) in present.iter().enumerate() {
+                for j in present[n + 1..].iter() {
+                    *counts.entry((*i, *j)).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let mut term_sim = TermSimilarity::new();
+        counts.iter().for_each(|((i, j), co)| {
+            let norm = ((self.document_frequency[*i] * self.document_frequency[*j]) as f64).sqrt();
+            let similarity = *co as f64 / norm;
+            if similarity >= min_similarity {
+                term_sim.insert(terms[i], terms[j], similarity);
+            }
+        });
+        term_sim
+    }
+
+    // terms with their number of occurrences, in order of first occurrence.
+    fn count_terms(terms: &[String]) -> Vec<(String, usize)> {
+        let mut positions: HashMap<&str, usize> = HashMap::new();
+        let mut counts: Vec<(String, usize)> = vec![];
+        for term in terms {
+            match positions.get(term.as_str()) {
+                Some(position) => counts[*position].1 += 1,
+                None => {
+                    positions.insert(term, counts.len());
+                    counts.push((term.clone(), 1));
+                }
+            }
+        }
+        counts
+    }
+
+    // gives unseen terms the next free column so existing columns keep their index. rows of earlier
+    // documents are left short until pad_term_frequencies.
+    fn add_columns(&mut self, counts: &[(String, usize)]) {
+        if self.vectorizer.settings.frozen_vocabulary { return; }
+
+        for (term, _) in counts.iter() {
+            if self.column(term).is_none() {
+                self.vectorizer.dictionary.insert(term.clone());
+                self.vector_length += 1;
+                self.document_frequency.push(0);
+                self.collection_frequency.push(0);
+            }
+        }
+    }
+
+    // the term frequency row for the counts, counting the document once in each term's document frequency
+    // and every occurrence in its collection frequency.
+    fn count_row(&mut self, counts: &[(String, usize)]) -> Vec<usize> {
+        let mut term_frequencies = vec![0; self.vector_length];
+        // terms without a column only occur with settings.frozen_vocabulary.
+        for (term, count) in counts.iter() {
+            let term_index = match self.column(term) {
+                Some(term_index) => term_index,
+                None => continue,
+            };
+            if term_frequencies[term_index] == 0 && *count > 0 {
+                self.document_frequency[term_index] += 1;
+            }
+            term_frequencies[term_index] += count;
+            self.collection_frequency[term_index] += *count as u64;
+        }
+        term_frequencies
+    }
+
+    // appends a document from its (normalized) term counts. weights are left for the caller to recompute.
+    fn add_counts(&mut self, document: T, counts: &[(String, usize)]) {
+        self.add_columns(counts);
+        let term_frequencies = self.count_row(counts);
+        self.term_frequencies.push(term_frequencies);
+        self.documents.push(document);
+    }
+
+    fn pad_term_frequencies(&mut self) {
+        let vector_length = self.vector_length;
+        self.term_frequencies.iter_mut().for_each(|document| document.resize(vector_length, 0));
+    }
+
+    // every term of the document, and the plain tokens of get_data in order (no bigrams or field:term columns).
+    fn document_terms(&mut self, doc: &T) -> (Vec<String>, Vec<String>) {
+        let words = Model::<T>::words(&doc.get_data(), &self.vectorizer.pipeline, &self.vectorizer.settings, false);
+        let tokens = Model::<T>::unigrams(&words, &self.vectorizer.settings);
+        let mut processed = tokens.clone();
+        processed.extend(Model::<T>::bigrams(&words, &self.vectorizer.settings));
+        // fields get their own field:term columns next to the plain terms of get_data.
+        for (field, text) in doc.get_fields() {
+            Model::<T>::preprocess_text(&text, &self.vectorizer.pipeline, &self.vectorizer.settings, false).iter()
+                .for_each(|term| processed.push(Model::<T>::scoped_term(&field, term)));
+            self.fields.insert(field);
+        }
+        (processed, tokens)
+    }
+
+    // documents indexed before store_positions was set are given empty sequences.
+    fn record_positions(&mut self, doc_index: usize, tokens: &[String]) {
+        if !self.vectorizer.settings.store_positions { return; }
+
+        let sequence = tokens.iter().filter_map(|token| self.column(token)).collect::<Vec<_>>();
+        if doc_index >= self.positions.len() {
+            self.positions.resize(doc_index + 1, vec![]);
+        }
+        self.positions[doc_index] = sequence;
+    }
+
+    pub fn token_sequence(&self, doc_index: usize) -> Option<Vec<usize>> {
+        if !self.vectorizer.settings.store_positions { return None; }
+        self.positions.get(doc_index).cloned()
+    }
+
+    fn index_documents(&mut self, documents: Vec<T>) {
+        self.index_documents_with_progress(documents, &mut |_, _| {});
+    }
+
+    fn index_documents_with_progress<F: FnMut(usize, usize)>(&mut self, documents: Vec<T>, progress: &mut F) {
+        let total = documents.len();
+        let interval = (total / 100).max(1);
+
+        for (i, doc) in documents.into_iter().enumerate() {
+            let (terms, tokens) = self.document_terms(&doc);
+            let counts = Model::<T>::count_terms(&terms);
+            self.add_counts(doc, &counts);
+            self.record_positions(self.documents.len() - 1, &tokens);
+
+            if (i + 1) % interval == 0 && i + 1 < total {
+                progress(i + 1, total);
+            }
+        }
+        self.pad_term_frequencies();
+        self.calculate_document_weights();
+        progress(total, total);
+    }
+
+    // indexes the queued documents, returning how many new terms they added to the vocabulary (always 0 with
+    // hashing_features or frozen_vocabulary).
+    pub fn update_index(&mut self) -> usize {
+        if self.queued_for_indexing.is_empty() {
+            println!("No documents queued");
+            return 0;
+        }
+
+        let queued = self.queued_for_indexing.drain(..).collect::<Vec<_>>();
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("update_index", documents = queued.len()).entered();
+        #[cfg(feature = "tracing")]
+        let start = Instant::now();
+        let vector_length = self.vector_length;
+
+        self.index_documents(queued);
+
+        let new_terms = self.vector_length - vector_length;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(new_terms, duration = ?start.elapsed(), "update_index done");
+        new_terms
+    }
+
+    // replaces a document in place, keeping every document index stable. out of range indices are ignored.
+    pub fn update_document(&mut self, doc_index: usize, new_doc: T) {
+        if doc_index >= self.documents.len() { return; }
+
+        let (terms, tokens) = self.document_terms(&new_doc);
+        let counts = Model::<T>::count_terms(&terms);
+
+        let old_columns = Model::<T>::nonzero_columns(&self.term_frequencies[doc_index]);
+
+        // take the old version out of the frequencies before counting the new one.
+        self.uncount_row(doc_index);
+
+        self.add_columns(&counts);
+        self.term_frequencies[doc_index] = self.count_row(&counts);
+        self.documents[doc_index] = new_doc;
+        self.record_positions(doc_index, &tokens);
+
+        self.pad_term_frequencies();
+        self.recalculate_columns(doc_index, &old_columns);
+    }
+
+    fn nonzero_columns(document: &[usize]) -> Vec<usize> {
+        document.iter().enumerate().filter(|(_, tf)| **tf > 0).map(|(i, _)| i).collect::<Vec<_>>()
+    }
+
+    // the weights after replacing one document's row. the number of documents is unchanged, so only the idf
+    // of the columns the old or new version uses can have moved: the document itself is weighed anew and
+    // the others are only touched in those columns, found through the (patched) inverted index. adding or
+    // removing documents changes every idf and goes through calculate_document_weights instead.
+    fn recalculate_columns(&mut self, doc_index: usize, old_columns: &[usize]) {
+        if self.weights_are_lazy {
+            self.calculate_document_weights();
+            return;
+        }
+
+        let vector_length = self.vector_length;
+        let new_columns = Model::<T>::nonzero_columns(&self.term_frequencies[doc_index]);
+
+        if let Some(inverted_index) = self.inverted_index.get_mut() {
+            inverted_index.resize(vector_length, vec![]);
+            for column in old_columns.iter() {
+                inverted_index[*column].retain(|i| *i != doc_index);
+            }
+            for column in new_columns.iter() {
+                let postings = &mut inverted_index[*column];
+                if let Err(position) = postings.binary_search(&doc_index) {
+                    postings.insert(position, doc_index);
+                }
+            }
+        }
+
+        let mut columns = old_columns.iter().chain(new_columns.iter()).copied().collect::<Vec<_>>();
+        columns.sort_unstable();
+        columns.dedup();
+
+        self.vectorizer.idf.resize(vector_length, 0f64);
+        for column in columns.iter() {
+            self.vectorizer.idf[*column] = self.fitted_idf(*column);
+        }
+
+        self.document_weights.iter_mut().for_each(|row| row.resize(vector_length, 0f64));
+        self.document_weights[doc_index] = self.weigh_document(&self.term_frequencies[doc_index], &self.vectorizer.idf);
+
+        self.inverted_index();
+        let inverted_index = self.inverted_index.get().unwrap();
+        for column in columns {
+            let factor = pos_factor(&self.vectorizer.settings, &self.vectorizer.dictionary, column);
+            for i in inverted_index[column].iter() {
+                let tf = self.term_frequencies[*i][column] as f64;
+                self.document_weights[*i][column] = self.vectorizer.settings.smart.weight(tf, self.vectorizer.idf[column], self.vectorizer.settings.log_base, false)
+                    * factor;
+            }
+        }
+
+        // the inverted index was patched above, only the weight derived caches go.
+        self.invalidate_weight_caches();
+    }
+
+    // the inverse of count_row for an indexed document.
+    fn uncount_row(&mut self, doc_index: usize) {
+        let document_frequency = &mut self.document_frequency;
+        let collection_frequency = &mut self.collection_frequency;
+        self.term_frequencies[doc_index].iter().enumerate()
+            .filter(|(_, tf)| **tf > 0)
+            .for_each(|(i, tf)| {
+                document_frequency[i] -= 1;
+                collection_frequency[i] -= *tf as u64;
+            });
+    }
+
+    // takes a document out of the index, shifting the indices of every later document down by one.
+    // its terms keep their columns (with a document frequency of 0 if nothing else uses them).
+    pub fn remove_document(&mut self, doc_index: usize) -> Option<T> {
+        if doc_index >= self.documents.len() { return None; }
+
+        self.uncount_row(doc_index);
+        self.term_frequencies.remove(doc_index);
+        if doc_index < self.positions.len() {
+            self.positions.remove(doc_index);
+        }
+        if doc_index < self.static_rank.len() {
+            self.static_rank.remove(doc_index);
+        }
+        let removed = self.documents.remove(doc_index);
+
+        self.calculate_document_weights();
+        Some(removed)
+    }
+
+    // occurrences of every indexed term (field:term ones included) across the corpus.
+    pub fn corpus_token_count(&self) -> u64 {
+        self.collection_frequency.iter().sum()
+    }
+
+    // adds a document from precomputed term counts instead of its text. the terms still go through
+    // preprocessing so they line up with the vocabulary.
+    pub fn insert_counted(&mut self, counts: HashMap<String, usize>, document: T) {
+        // by term, so new terms get their columns in the same order on every run.
+        let mut entries = counts.iter().filter(|(_, count)| **count > 0).collect::<Vec<_>>();
+        entries.sort();
+        let mut normalized: Vec<(String, usize)> = vec![];
+        for (term, count) in entries {
+            for token in Model::<T>::preprocess_text(term, &self.vectorizer.pipeline, &self.vectorizer.settings, false) {
+                match normalized.iter_mut().find(|(t, _)| *t == token) {
+                    Some(existing) => existing.1 += count,
+                    None => normalized.push((token, *count)),
+                }
+            }
+        }
+
+        self.add_counts(document, &normalized);
+        // counts carry no order, the document gets an empty sequence.
+        self.record_positions(self.documents.len() - 1, &[]);
+        self.pad_term_frequencies();
+        self.calculate_document_weights();
+    }
+
+    // approximate heap footprint in bytes of the index state and of whichever caches are built, excluding the
+    // documents themselves.
+    pub fn memory_estimate(&self) -> usize {
+        let weights = self.document_weights.iter()
+            .chain(self.lazy_weights.iter().filter_map(|row| row.get()))
+            .map(|row| size_of::<Vec<f64>>() + row.len() * size_of::<f64>())
+            .sum::<usize>();
+        let frequencies = self.term_frequencies.iter()
+            .map(|row| size_of::<Vec<usize>>() + row.len() * size_of::<usize>())
+            .sum::<usize>();
+        let document_frequency = (self.document_frequency.len() + self.collection_frequency.len()) * size_of::<u64>();
+        let dictionary = self.vectorizer.dictionary.iter()
+            .map(|term| size_of::<String>() + term.len())
+            .sum::<usize>();
+
+        let positions = self.positions.iter()
+            .map(|row| size_of::<Vec<usize>>() + row.len() * size_of::<usize>())
+            .sum::<usize>();
+        let ranks_and_idf = (self.static_rank.len() + self.vectorizer.idf.len()) * size_of::<f64>();
+        let background_idf = self.background_idf.iter()
+            .flat_map(|(background_df, _)| background_df.keys())
+            .map(|term| size_of::<String>() + term.len() + size_of::<u64>())
+            .sum::<usize>();
+
+        let norms = self.norms.get().map_or(0, |norms| norms.len() * size_of::<f64>());
+        let inverted_index = self.inverted_index.get().iter()
+            .flat_map(|postings| postings.iter())
+            .map(|postings| size_of::<Vec<usize>>() + postings.len() * size_of::<usize>())
+            .sum::<usize>();
+        let lsh = self.lsh.get().iter()
+            .flat_map(|tables| tables.iter())
+            .map(|table| {
+                let hyperplanes = table.hyperplanes.iter()
+                    .map(|hyperplane| size_of::<Vec<f64>>() + hyperplane.len() * size_of::<f64>())
+                    .sum::<usize>();
+                let buckets = table.buckets.values()
+                    .map(|bucket| size_of::<u64>() + size_of::<Vec<usize>>() + bucket.len() * size_of::<usize>())
+                    .sum::<usize>();
+                hyperplanes + buckets
+            })
+            .sum::<usize>();
+
+        weights + frequencies + document_frequency + dictionary + positions + ranks_and_idf + background_idf
+            + norms + inverted_index + lsh
+    }
+
+    // term -> (document index, term frequency) for every document containing the term, by document index.
+    pub fn postings(&self) -> HashMap<String, Vec<(usize, usize)>> {
+        self.vectorizer.dictionary.iter().enumerate().map(|(column, term)| {
+            let list = self.term_frequencies.iter().enumerate()
+                .filter(|(_, document)| document[column] > 0)
+                .map(|(i, document)| (i, document[column]))
+                .collect::<Vec<_>>();
+            (term.clone(), list)
+        }).collect::<HashMap<_, _>>()
+    }
+
+    // for each of self's columns, the column holding the same term in other.
+    pub fn align_to(&self, other: &Model<T>) -> Vec<Option<usize>> {
+        let mut alignment = vec![None; self.vector_length];
+        self.vectorizer.dictionary.iter().enumerate().for_each(|(i, term)| {
+            alignment[i] = other.column(term);
+        });
+        alignment
+    }
+
+    pub fn freeze(mut self) -> FrozenModel<T> {
+        self.queued_for_indexing = vec![];
+        FrozenModel { model: self }
+    }
+
+    // rebuilds document_frequency from term_frequencies, e.g. after manual edits. weights are left as is,
+    // follow up with calculate_document_weights.
+    pub fn recompute_document_frequency(&mut self) {
+        let mut document_frequency = vec![0; self.vector_length];
+        for document in self.term_frequencies.iter() {
+            document.iter().enumerate()
+                .filter(|(_, tf)| **tf > 0)
+                .for_each(|(i, _)| document_frequency[i] += 1);
+        }
+        self.document_frequency = document_frequency;
+    }
+
+    // idf of a column, from the background statistics when the term is known there.
+    pub fn idf(&self, column: usize) -> f64 {
+        if !self.vectorizer.settings.use_idf { return 1f64; }
+
+        let (df, n) = self.background_idf.as_ref()
+            .and_then(|(background_df, background_n)| {
+                let df = self.vectorizer.dictionary.get_index(column).and_then(|term| background_df.get(term))?;
+                Some((*df, *background_n)).filter(|(df, _)| *df > 0)
+            })
+            .unwrap_or((self.document_frequency[column], self.documents.len()));
+
+        match self.idf_fn {
+            Some(idf_fn) => idf_fn(df, n),
+            None => Model::<T>::calc_idf(df, n, self.vectorizer.settings.log_base),
+        }
+    }
+
+    // idf as the vectorizer keeps it, 0 for columns no document uses.
+    fn fitted_idf(&self, column: usize) -> f64 {
+        if self.document_frequency[column] == 0 { return 0f64; }
+        self.idf(column)
+    }
+
+    // a quality signal per document (e.g. link popularity) that search multiplies in as score * (1 + rank).
+    // ranks are scaled into [0, 1] by the largest one, negative ranks count as 0 and documents past the end
+    // of ranks (like ones indexed later) get 0.
+    pub fn set_static_rank(&mut self, ranks: Vec<f64>) {
+        let max = ranks.iter().copied().fold(0f64, f64::max);
+        self.static_rank = ranks.iter()
+            .map(|rank| if max > 0f64 { rank.max(0f64) / max } else { 0f64 })
+            .collect::<Vec<_>>();
+    }
+
+    // use f(document frequency, number of documents) as idf for documents and queries, weights are recomputed.
+    pub fn set_idf_fn(&mut self, f: fn(df: u64, n: usize) -> f64) {
+        self.idf_fn = Some(f);
+        self.calculate_document_weights();
+    }
+
+    // sets settings.pos_weight, weights are recomputed.
+    pub fn set_pos_weight(&mut self, f: Option<fn(&str) -> f64>) {
+        self.vectorizer.settings.pos_weight = f;
+        self.calculate_document_weights();
+    }
+
+    fn weigh_document(&self, document: &[usize], idf: &[f64]) -> Vec<f64> {
+        document.iter().enumerate().map(|(i, tf)| {
+            if *tf == 0 { return 0f64; }
+            self.vectorizer.settings.smart.weight(*tf as f64, idf[i], self.vectorizer.settings.log_base, false)
+                * pos_factor(&self.vectorizer.settings, &self.vectorizer.dictionary, i)
+        }).collect::<Vec<_>>()
+    }
+
+    pub fn calculate_document_weights(&mut self) {
+        let idf = (0..self.vector_length).map(|i| self.fitted_idf(i)).collect::<Vec<_>>();
+
+        self.weights_are_lazy = self.vectorizer.settings.lazy_weights;
+        if self.weights_are_lazy {
+            self.document_weights = vec![];
+            self.lazy_weights = self.documents.iter().map(|_| OnceLock::new()).collect();
+        } else {
+            self.document_weights = self.term_frequencies.iter()
+                .map(|document| self.weigh_document(document, &idf))
+                .collect::<Vec<Vec<f64>>>();
+            self.lazy_weights = vec![];
+        }
+        self.vectorizer.idf = idf;
+        self.invalidate_caches();
+    }
+
+    // drops the norms, inverted index and lsh tables so the next search rebuilds them. the methods adding,
+    // removing or replacing documents do this themselves, as does calculate_document_weights, which is needed
+    // anyway after changing a setting the weights depend on (smart, log_base, use_idf, ...). call it after
+    // editing term_frequencies or document_weights by hand, or after changing ann_tables, ann_bits or ann_seed.
+    pub fn invalidate_caches(&mut self) {
+        self.inverted_index = OnceLock::new();
+        self.invalidate_weight_caches();
+    }
+
+    fn invalidate_weight_caches(&mut self) {
+        self.norms = OnceLock::new();
+        self.lsh = OnceLock::new();
+    }
+
+    // euclidean length of every document's weight vector.
+    pub fn document_norms(&self) -> &[f64] {
+        self.norms.get_or_init(|| {
+            self.weight_rows().map(Model::<T>::euclidean_len).collect::<Vec<_>>()
+        })
+    }
+
+    // column -> indices of the documents containing that term, ascending.
+    pub fn inverted_index(&self) -> &[Vec<usize>] {
+        self.inverted_index.get_or_init(|| {
+            let mut inverted_index = vec![vec![]; self.vector_length];
+            for (i, document) in self.term_frequencies.iter().enumerate() {
+                document.iter().enumerate()
+                    .filter(|(_, tf)| **tf > 0)
+                    .for_each(|(column, _)| inverted_index[column].push(i));
+            }
+            inverted_index
+        })
+    }
+
+    // builds the lazy weights, norms and inverted index up front so the first query doesn't pay for them. the
+    // lsh tables are left to the first search_ann, most models never need them.
+    pub fn warm_up(&mut self) {
+        (0..self.documents.len()).for_each(|i| { self.weights_of(i); });
+        self.document_norms();
+        self.inverted_index();
+    }
+
+    fn lsh(&self) -> &[Lsh] {
+        self.lsh.get_or_init(|| {
+            let rows = self.weight_rows().collect::<Vec<_>>();
+            let mut state = self.vectorizer.settings.ann_seed.max(1);
+            (0..self.vectorizer.settings.ann_tables)
+                .map(|_| Lsh::new(&rows, self.vector_length, self.vectorizer.settings.ann_bits, &mut state))
+                .collect::<Vec<_>>()
+        })
+    }
+
+    // zeroes every NaN or infinite weight, returning how many were replaced.
+    pub fn sanitize_weights(&mut self) -> usize {
+        let mut fixed = 0;
+        let lazy = self.lazy_weights.iter_mut().filter_map(|row| row.get_mut());
+        for row in self.document_weights.iter_mut().chain(lazy) {
+            for weight in row.iter_mut().filter(|weight| !weight.is_finite()) {
+                *weight = 0f64;
+                fixed += 1;
+            }
+        }
+        if fixed > 0 {
+            self.invalidate_weight_caches();
+        }
+        fixed
+    }
+
+    pub fn weights_of(&self, doc_index: usize) -> &[f64] {
+        if !self.weights_are_lazy {
+            return &self.document_weights[doc_index];
+        }
+        self.lazy_weights[doc_index]
+            .get_or_init(|| self.weigh_document(&self.term_frequencies[doc_index], &self.vectorizer.idf))
+    }
+
+    // one "label column:weight ..." line per document (1-based columns, nonzero weights only), labels[i] being
+    // document i's label. documents past the end of labels are left out.
+    pub fn to_libsvm(&self, labels: &[i32]) -> String {
+        labels.iter().enumerate().take(self.documents.len()).map(|(i, label)| {
+            let features = self.sparse_weights(i).unwrap().iter()
+                .map(|(column, weight)| format!(" {}:{}", column + 1, weight))
+                .collect::<String>();
+            format!("{}{}\n", label, features)
+        }).collect::<String>()
+    }
+
+    // the nonzero (column, weight) pairs of a document, by column.
+    pub fn sparse_weights(&self, doc_index: usize) -> Option<Vec<(usize, f64)>> {
+        if doc_index >= self.documents.len() { return None; }
+
+        Some(self.weights_of(doc_index).iter().enumerate()
+            .filter(|(_, weight)| **weight != 0f64)
+            .map(|(i, weight)| (i, *weight))
+            .collect::<Vec<_>>())
+    }
+
+    pub fn weight_rows(&self) -> impl Iterator<Item = &[f64]> + '_ {
+        (0..self.documents.len()).map(move |i| self.weights_of(i))
+    }
+
+    // an empty documents vec gives a valid empty model: every search and ranking returns no results (score
+    // gives None) until documents are added through insert_docs and update_index.
+    pub fn construct(documents: Vec<T>, processing_capture: fn(cap: &Captures) -> String, processing_regex: Regex) -> Self {
+        Model::construct_with_pipeline(documents, vec![(processing_regex, processing_capture)])
+    }
+
+    pub fn construct_with_pipeline(documents: Vec<T>, pipeline: Pipeline) -> Self {
+        Model::construct_with_settings(documents, pipeline, Settings::default())
+    }
+
+    pub fn construct_with_settings(documents: Vec<T>, pipeline: Pipeline, settings: Settings) -> Self {
+        Model::construct_with_progress(documents, pipeline, settings, |_, _| {})
+    }
+
+    // construct_with_settings calling progress(processed, total) about every 1% of the documents while they
+    // are tokenized and counted, and a last time with (total, total) once the weights are computed.
+    pub fn construct_with_progress<F: FnMut(usize, usize)>(documents: Vec<T>, pipeline: Pipeline, settings: Settings, mut progress: F) -> Self {
+        let mut model = Self {
+            vector_length: 0,
+            vectorizer: Vectorizer::new(pipeline, settings),
+            queued_for_indexing: vec![],
+            document_weights: vec![],
+            term_frequencies: vec![],
+            document_frequency: vec![],
+            collection_frequency: vec![],
+            documents: vec![],
+            background_idf: None,
+            fields: HashSet::new(),
+            positions: vec![],
+            static_rank: vec![],
+            idf_fn: None,
+            weights_are_lazy: false,
+            lazy_weights: vec![],
+            norms: OnceLock::new(),
+            inverted_index: OnceLock::new(),
+            lsh: OnceLock::new(),
+        };
+
+        if let Some(n) = model.hashing_features() {
+            model.vector_length = n;
+            model.document_frequency = vec![0; n];
+            model.collection_frequency = vec![0; n];
+        }
+
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("construct", documents = documents.len()).entered();
+        #[cfg(feature = "tracing")]
+        let start = Instant::now();
+
+        model.index_documents_with_progress(documents, &mut progress);
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(vector_length = model.vector_length, duration = ?start.elapsed(), "construct done");
+        model
+    }
+
+    // idf comes from the background statistics, term frequencies from the documents. terms the background
+    // does not know fall back to the documents' own document frequency.
+    pub fn with_background_idf(documents: Vec<T>, background_df: HashMap<String, u64>, background_n: usize, pipeline: Pipeline, settings: Settings) -> Self {
+        let mut model = Model::construct_with_settings(vec![], pipeline, settings);
+        model.background_idf = Some((background_df, background_n));
+        model.index_documents(documents);
+        model
+    }
+
+    fn stored(&self) -> StoredModelRef<'_, T> {
+        StoredModelRef {
+            vectorizer: &self.vectorizer,
+            vector_length: self.vector_length,
+            term_frequencies: &self.term_frequencies,
+            document_frequency: &self.document_frequency,
+            collection_frequency: &self.collection_frequency,
+            documents: &self.documents,
+            background_idf: &self.background_idf,
+            fields: &self.fields,
+            positions: &self.positions,
+            static_rank: &self.static_rank,
+        }
+    }
+
+    pub fn save_binary<W: Write>(&self, writer: W) -> Result<(), ModelError> where T: Serialize {
+        // varint encoding, the (mostly small) frequencies take a byte or two instead of eight.
+        bincode::DefaultOptions::new().serialize_into(writer, &self.stored()).map_err(|e| ModelError::Serialization(e.to_string()))
+    }
+
+    // captures are matched to the stored pipeline's stages in order. a custom idf function is a plain function
+    // too and is not stored (like the stemmer and pos_weight): the loaded model weighs with calc_idf until
+    // set_idf_fn is called on it again.
+    pub fn load_binary<R: Read>(reader: R, captures: &[fn(cap: &Captures) -> String]) -> Result<Model<T>, ModelError> where T: DeserializeOwned {
+        let stored: StoredModel<T> = bincode::DefaultOptions::new().deserialize_from(reader)
+            .map_err(|e| ModelError::Serialization(e.to_string()))?;
+
+        let vectorizer = stored.vectorizer.with_captures(captures)?;
+
+        let mut model = Model::construct_with_settings(vec![], vectorizer.pipeline.clone(), vectorizer.settings.clone());
+        model.vectorizer = vectorizer;
+        model.vector_length = stored.vector_length;
+        model.term_frequencies = stored.term_frequencies;
+        model.document_frequency = stored.document_frequency;
+        model.collection_frequency = stored.collection_frequency;
+        model.documents = stored.documents;
+        model.background_idf = stored.background_idf;
+        model.fields = stored.fields;
+        model.positions = stored.positions;
+        model.static_rank = stored.static_rank;
+
+        model.calculate_document_weights();
+        Ok(model)
+    }
+}
+
+// a built model that can only be read and searched, handing out &Model but never &mut Model.
+pub struct FrozenModel<T> {
+    model: Model<T>,
+}
+
+impl<T> Deref for FrozenModel<T> {
+    type Target = Model<T>;
+
+    fn deref(&self) -> &Model<T> {
+        &self.model
+    }
+}
+
+// what a model learns from its corpus (vocabulary, idf, preprocessing), the part of Model that maps texts
+// into its feature space. taken out of a model (or fitted) it maps other texts into the same space. the
+// pipeline's captures are plain functions and are not serialized, a deserialized vectorizer needs them back
+// through with_captures before it can transform.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Vectorizer {
+    pub settings: Settings,
+    pub pipeline_sources: Vec<String>,
+    #[serde(skip)]
+    pub pipeline: Pipeline,
+    // column order, empty under settings.hashing_features.
+    pub dictionary: IndexSet<String>,
+    // idf of every column, 0 for columns no document used.
+    pub idf: Vec<f64>,
+}
+
+impl Vectorizer {
+    // an unfitted vectorizer, no vocabulary yet.
+    pub fn new(pipeline: Pipeline, settings: Settings) -> Self {
+        Vectorizer {
+            settings,
+            pipeline_sources: pipeline.iter().map(|(regex, _)| regex.as_str().to_string()).collect(),
+            pipeline,
+            dictionary: IndexSet::new(),
+            idf: vec![],
+        }
+    }
+
+    pub fn fit<T: Document + Debug + Clone>(documents: Vec<T>, pipeline: Pipeline, settings: Settings) -> Self {
+        Model::construct_with_settings(documents, pipeline, settings).vectorizer
+    }
+
+    pub fn with_captures(mut self, captures: &[fn(cap: &Captures) -> String]) -> Result<Self, ModelError> {
+        self.pipeline = compile_pipeline(&self.pipeline_sources, captures)?;
+        Ok(self)
+    }
+
+    pub fn vector_length(&self) -> usize {
+        self.idf.len()
+    }
+
+    pub fn column(&self, term: &str) -> Option<usize> {
+        match self.settings.hashing_features.filter(|n| *n > 0) {
+            Some(n) => Some((Model::<String>::fnv1a(term) % n as u64) as usize),
+            None => self.dictionary.get_index_of(term),
+        }
+    }
+
+    // tf-idf vectors of the texts, terms outside the fitted vocabulary are dropped.
+    pub fn transform(&self, texts: &[String]) -> Vec<Vec<f64>> {
+        texts.iter().map(|text| self.vector(text)).collect::<Vec<_>>()
+    }
+
+    // the tf-idf vector of one text, weighed like a corpus document (no binary_query cap).
+    pub fn vector(&self, text: &str) -> Vec<f64> {
+        let mut term_frequencies = vec![0; self.vector_length()];
+        Model::<String>::preprocess_text(text, &self.pipeline, &self.settings, false).iter()
+            .filter_map(|term| self.column(term))
+            .for_each(|column| term_frequencies[column] += 1);
+
+        term_frequencies.iter().enumerate()
+            .map(|(i, tf)| {
+                if *tf == 0 { return 0f64; }
+                self.settings.smart.weight(*tf as f64, self.idf[i], self.settings.log_base, false)
+                    * pos_factor(&self.settings, &self.dictionary, i)
+            })
+            .collect::<Vec<_>>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+    fn space(_: &Captures) -> String {
+        String::from(" ")
+    }
+
+    // splits on everything that is not a word character.
+    fn pipeline() -> Pipeline {
+        let stage: Stage = (Regex::new(r"\W+").unwrap(), space);
+        vec![stage]
+    }
+
+    fn strings(docs: &[&str]) -> Vec<String> {
+        docs.iter().map(|doc| doc.to_string()).collect()
+    }
+
+    fn model(docs: &[&str]) -> Model<String> {
+        Model::construct_with_pipeline(strings(docs), pipeline())
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    fn texts(results: &[(String, f64)]) -> Vec<&str> {
+        results.iter().map(|(doc, _)| doc.as_str()).collect()
+    }
+
+    fn model_with(docs: &[&str], settings: Settings) -> Model<String> {
+        Model::construct_with_settings(strings(docs), pipeline(), settings)
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Article {
+        title: String,
+        body: String,
+    }
+
+    impl Article {
+        fn new(title: &str, body: &str) -> Self {
+            Article { title: title.to_string(), body: body.to_string() }
+        }
+    }
+
+    impl Document for Article {
+        fn get_data(&self) -> String {
+            format!("{} {}", self.title, self.body)
+        }
+
+        fn get_fields(&self) -> Vec<(String, String)> {
+            vec![(String::from("title"), self.title.clone()), (String::from("body"), self.body.clone())]
+        }
+    }
+
+    fn articles() -> Vec<Article> {
+        vec![
+            Article::new("rust", "a systems language"),
+            Article::new("a systems language", "rust"),
+            Article::new("go", "simple and new york based"),
+        ]
+    }
+
+    static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+    // a document counting how often it is cloned.
+    #[derive(Debug)]
+    struct Counted(String);
+
+    impl Clone for Counted {
+        fn clone(&self) -> Self {
+            CLONES.fetch_add(1, AtomicOrdering::SeqCst);
+            Counted(self.0.clone())
+        }
+    }
+
+    impl Document for Counted {
+        fn get_data(&self) -> String {
+            self.0.clone()
+        }
+    }
+
+    #[test]
+    fn ranked_indices_matches_search_without_cloning() {
+        let docs = ["rust is fast", "rust rust and more rust", "go is simple", "python"];
+        let counted = Model::construct_with_pipeline(docs.iter().map(|doc| Counted(doc.to_string())).collect(), pipeline());
+        let before = CLONES.load(AtomicOrdering::SeqCst);
+        let ranked = counted.ranked_indices(String::from("rust fast"));
+        assert_eq!(CLONES.load(AtomicOrdering::SeqCst), before);
+
+        let searched = model(&docs).search(String::from("rust fast"));
+        assert_eq!(ranked.len(), searched.len());
+        for ((i, score), (doc, expected)) in ranked.iter().zip(searched.iter()) {
+            assert_eq!(docs[*i], doc);
+            assert!(close(*score, *expected));
+        }
+        assert!(ranked.iter().all(|(_, score)| *score > 0f64));
+    }
+
+    #[test]
+    fn soft_cosine_matches_similar_terms() {
+        let m = model(&["car repair", "automobile repair", "cooking pasta"]);
+        let mut term_sim = TermSimilarity::new();
+        term_sim.insert("car", "automobile", 0.8);
+
+        assert_eq!(Model::<String>::sim(&m.query_vector(String::from("car")), m.weights_of(1)), 0f64);
+        let results = m.search_soft_cosine(String::from("car"), &term_sim);
+        assert_eq!(texts(&results), vec!["car repair", "automobile repair"]);
+        assert!(results[1].1 > 0f64);
+    }
+
+    fn stopwords(words: &[&str]) -> Settings {
+        Settings { stopwords: words.iter().map(|word| word.to_string()).collect(), ..Settings::default() }
+    }
+
+    #[test]
+    fn stopword_only_corpus_searches_without_panicking() {
+        let mut m = model_with(&["the a", "a of the", ""], stopwords(&["the", "a", "of"]));
+        assert_eq!(m.vector_length, 0);
+        m.calculate_document_weights();
+        assert!(m.search(String::from("the rust")).is_empty());
+        assert_eq!(m.score(String::from("rust"), 0), Some(0f64));
+    }
+
+    #[test]
+    fn rarest_and_common_terms_follow_document_frequency() {
+        let m = model(&["rust go", "rust python", "rust go zig"]);
+        assert_eq!(m.rarest_terms(2), vec![(String::from("python"), 1), (String::from("zig"), 1)]);
+        assert_eq!(m.common_terms(2), vec![(String::from("rust"), 3), (String::from("go"), 2)]);
+    }
+
+    #[test]
+    fn pipeline_stages_run_in_order_for_documents_and_queries() {
+        let urls: Stage = (Regex::new(r"https?://\S+").unwrap(), space);
+        let punctuation: Stage = (Regex::new(r"[^A-Za-z0-9 ]").unwrap(), space);
+        let m = Model::construct_with_pipeline(strings(&["see https://example.com/rust, now!", "later"]), vec![urls, punctuation]);
+
+        assert_eq!(m.sorted_vocabulary(), vec!["later", "now", "see"]);
+        assert!(!m.search(String::from("now!")).is_empty());
+        assert!(m.search(String::from("https://example.com/rust")).is_empty());
+    }
+
+    #[test]
+    fn memory_estimate_grows_with_documents() {
+        let mut m = model(&["rust is fast", "go is simple"]);
+        let before = m.memory_estimate();
+        m.insert_docs(strings(&["python is dynamic and popular"]));
+        m.update_index();
+        let indexed = m.memory_estimate();
+        assert!(indexed > before);
+
+        // the caches count once they are built, the lsh tables hold ann_tables * ann_bits hyperplanes.
+        m.warm_up();
+        let warm = m.memory_estimate();
+        assert!(warm > indexed);
+        m.search_ann(String::from("rust"), 1, 1);
+        let hyperplanes = m.vectorizer.settings.ann_tables * m.vectorizer.settings.ann_bits * m.vector_length;
+        assert!(m.memory_estimate() >= warm + hyperplanes * size_of::<f64>());
+    }
+
+    #[test]
+    fn search_timed_reports_every_phase() {
+        let m = model(&["rust is fast", "go is simple", "python"]);
+        let (results, timings) = m.search_timed(String::from("rust"));
+        assert_eq!(texts(&results), texts(&m.search(String::from("rust"))));
+
+        let total = timings.preprocessing + timings.query_weights + timings.scoring + timings.sorting;
+        assert!(total >= timings.scoring);
+    }
+
+    fn assert_send_sync<S: Send + Sync>(_: &S) {}
+
+    #[test]
+    fn frozen_model_searches_and_is_send_sync() {
+        let m = model(&["rust is fast", "go is simple"]);
+        let expected = m.search(String::from("rust"));
+        let frozen = m.freeze();
+        assert_send_sync(&frozen);
+        assert!(frozen.queued_for_indexing.is_empty());
+        assert_eq!(frozen.search(String::from("rust")), expected);
+    }
+
+    #[test]
+    fn similar_to_text_finds_the_pasted_document() {
+        let docs = ["rust is a fast systems language", "go is simple", "python is dynamic", "cooking pasta quickly"];
+        let m = model(&docs);
+        let results = m.similar_to_text(String::from(docs[2]), 2);
+        assert_eq!(results[0].0, docs[2]);
+        assert!(results.len() <= 2);
+
+        let duplicated = model_with(&["go is simple", "go is simple", "go fast", "pasta"], Settings { dedup: true, ..Settings::default() });
+        assert_eq!(texts(&duplicated.similar_to_text(String::from("simple go"), 3)), vec!["go is simple", "go fast"]);
+    }
+
+    #[test]
+    fn alpha_only_drops_tokens_with_digits() {
+        let m = model_with(&["rust2024 rust", "go"], Settings { alpha_only: true, ..Settings::default() });
+        assert_eq!(m.sorted_vocabulary(), vec!["go", "rust"]);
+        assert!(m.search(String::from("rust2024")).is_empty());
+    }
+
+    #[test]
+    fn postings_list_the_documents_using_a_term() {
+        let m = model(&["rust rust go", "go", "rust"]);
+        let postings = m.postings();
+        assert_eq!(postings["rust"], vec![(0, 2), (2, 1)]);
+        assert_eq!(postings["go"], vec![(0, 1), (1, 1)]);
+        assert_eq!(postings.len(), m.vector_length);
+    }
+
+    #[test]
+    fn transform_of_a_corpus_document_matches_its_weights() {
+        let docs = ["rust is fast fast", "go is simple", "python"];
+        let m = model(&docs);
+        let vectors = m.transform(&strings(&docs));
+        for (i, vector) in vectors.iter().enumerate() {
+            assert_eq!(vector.len(), m.vector_length);
+            assert!(vector.iter().zip(m.weights_of(i)).all(|(a, b)| close(*a, *b)));
+        }
+        assert!(m.transform(&strings(&["unknown words"]))[0].iter().all(|weight| *weight == 0f64));
+
+        // binary_query only caps the counts of queries, texts are weighed like documents.
+        let m = model_with(&docs, Settings { binary_query: true, ..Settings::default() });
+        let vectors = m.transform(&strings(&docs));
+        assert!(vectors[0].iter().zip(m.weights_of(0)).all(|(a, b)| close(*a, *b)));
+        assert!(vectors[0].iter().zip(&m.vectorizer.transform(&strings(&docs))[0]).all(|(a, b)| close(*a, *b)));
+    }
+
+    #[test]
+    fn recompute_document_frequency_repairs_counts() {
+        let mut m = model(&["rust rust go", "go", "rust"]);
+        let expected = m.document_frequency.clone();
+        assert_eq!(expected[m.column("rust").unwrap()], 2);
+
+        m.document_frequency.iter_mut().for_each(|df| *df = 7);
+        m.recompute_document_frequency();
+        assert_eq!(m.document_frequency, expected);
+    }
+
+    #[test]
+    fn nearest_by_vector_finds_the_document_of_a_weight_row() {
+        let m = model(&["rust is fast", "go is simple", "python is dynamic"]);
+        let row = m.weights_of(1).to_vec();
+        let nearest = m.nearest_by_vector(&row, 2, DistanceMetric::Cosine).unwrap();
+        assert_eq!(nearest[0].0, 1);
+        assert!(close(nearest[0].1, 1f64));
+        assert_eq!(nearest.len(), 2);
+
+        let error = m.nearest_by_vector(&[1f64], 2, DistanceMetric::Cosine).unwrap_err();
+        assert_eq!(error, ModelError::DimensionMismatch { expected: m.vector_length, found: 1 });
+    }
+
+    #[test]
+    fn search_sorted_by_breaks_ties_with_the_key() {
+        let m = model(&["rust 3", "rust 1", "go 2"]);
+        let key = |doc: &String| doc.split(' ').nth(1).unwrap().parse::<u32>().unwrap();
+        let results = m.search_sorted_by(String::from("rust"), key);
+        assert!(close(results[0].1, results[1].1));
+        assert_eq!(texts(&results), vec!["rust 1", "rust 3"]);
+    }
+
+    #[test]
+    fn indexed_stopwords_are_ignored_in_queries() {
+        let settings = Settings { index_stopwords: true, ..stopwords(&["the"]) };
+        let m = model_with(&["the rust", "go"], settings);
+        assert!(m.column("the").is_some());
+        assert!(m.search(String::from("the")).is_empty());
+        assert_eq!(texts(&m.search(String::from("the rust"))), vec!["the rust"]);
+    }
+
+    #[test]
+    fn align_to_maps_shared_terms() {
+        let a = model(&["rust go python"]);
+        let b = model(&["zig python", "rust"]);
+        let alignment = a.align_to(&b);
+        assert_eq!(alignment[a.column("rust").unwrap()], b.column("rust"));
+        assert_eq!(alignment[a.column("python").unwrap()], b.column("python"));
+        assert_eq!(alignment[a.column("go").unwrap()], None);
+    }
+
+    #[test]
+    fn log_base_changes_weights_predictably() {
+        let docs = ["rust rust rust rust go", "go", "python", "zig"];
+        let ten = model(&docs);
+        let two = model_with(&docs, Settings { log_base: 2f64, ..Settings::default() });
+        let column = ten.column("rust").unwrap();
+
+        // (1 + log_b 4) * log_b 4
+        assert!(close(ten.weights_of(0)[column], (1f64 + 4f64.log10()) * 4f64.log10()));
+        assert!(close(two.weights_of(0)[column], 3f64 * 2f64));
+        assert!(close(two.query_vector(String::from("rust"))[column], 2f64));
+    }
+
+    #[test]
+    fn insert_counted_documents_are_searchable() {
+        let mut m = model(&["rust is fast", "go is simple"]);
+        let counts = [(String::from("Zig"), 2), (String::from("rust"), 1)].iter().cloned().collect::<HashMap<_, _>>();
+        m.insert_counted(counts, String::from("zig zig rust"));
+
+        let column = m.column("zig").unwrap();
+        assert_eq!(m.term_frequencies[2][column], 2);
+        let idf = Model::<String>::calc_idf(1, 3, 10f64);
+        assert!(close(m.weights_of(2)[column], (1f64 + 2f64.log10()) * idf));
+        assert_eq!(texts(&m.search(String::from("zig"))), vec!["zig zig rust"]);
+
+        let counts = ["x", "y", "v", "z", "w"].iter().map(|term| (term.to_string(), 1)).collect::<HashMap<_, _>>();
+        m.insert_counted(counts, String::from("v w x y z"));
+        let columns = ["v", "w", "x", "y", "z"].iter().map(|term| m.column(term).unwrap()).collect::<Vec<_>>();
+        assert!(columns.windows(2).all(|pair| pair[0] + 1 == pair[1]));
+    }
+
+    #[test]
+    fn query_coverage_is_the_share_of_known_terms() {
+        let m = model(&["rust is fast", "go is simple"]);
+        assert!(close(m.query_coverage(String::from("rust go haskell")), 2f64 / 3f64));
+        assert_eq!(m.query_coverage(String::from("")), 0f64);
+    }
+
+    #[test]
+    fn rerank_scores_only_the_candidates() {
+        let m = model(&["rust is fast", "rust rust", "go", "rust and go"]);
+        let reranked = m.rerank(String::from("rust"), &[3, 1, 2, 99]);
+        assert_eq!(reranked.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![1, 3, 2]);
+        assert!(reranked.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+    }
+
+    #[test]
+    fn hashing_features_keep_vector_length_fixed() {
+        let mut m = model_with(&["rust is fast", "go is simple"], Settings { hashing_features: Some(1024), ..Settings::default() });
+        assert_eq!(m.vector_length, 1024);
+        m.insert_docs(strings(&["python haskell zig ocaml elixir", "rust"]));
+        assert_eq!(m.update_index(), 0);
+        assert_eq!(m.vector_length, 1024);
+        assert!(m.vectorizer.dictionary.is_empty());
+        assert_eq!(m.search(String::from("haskell"))[0].0, "python haskell zig ocaml elixir");
+    }
+
+    #[test]
+    fn search_with_index_returns_the_source_index() {
+        let docs = ["go is simple", "rust is fast", "python"];
+        let m = model(&docs);
+        let results = m.search_with_index(String::from("rust"));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+        assert_eq!(results[0].1, docs[1]);
+    }
+
+    #[test]
+    fn new_terms_do_not_move_existing_columns() {
+        let mut m = model(&["rust is fast", "go is simple"]);
+        let columns = ["rust", "go", "simple"].iter().map(|term| m.column(term)).collect::<Vec<_>>();
+        m.insert_docs(strings(&["python and zig", "a rust"]));
+        m.update_index();
+        assert_eq!(["rust", "go", "simple"].iter().map(|term| m.column(term)).collect::<Vec<_>>(), columns);
+        assert_eq!(m.column("zig"), Some(m.vector_length - 2));
+    }
+
+    #[test]
+    fn sorted_vocabulary_is_sorted_and_complete() {
+        let m = model(&["zig rust", "go ada rust"]);
+        let vocabulary = m.sorted_vocabulary();
+        assert_eq!(vocabulary, vec!["ada", "go", "rust", "zig"]);
+        assert_eq!(vocabulary.len(), m.vector_length);
+    }
+
+    #[test]
+    fn coordination_factor_prefers_documents_matching_more_terms() {
+        let docs = ["rust fast", "rust rust", "go", "python"];
+        let plain = model(&docs);
+        let m = model_with(&docs, Settings { coordination_factor: true, ..Settings::default() });
+
+        let both = plain.score(String::from("rust fast"), 0).unwrap();
+        let one = plain.score(String::from("rust fast"), 1).unwrap();
+        let results = m.search(String::from("rust fast"));
+        assert_eq!(texts(&results), vec!["rust fast", "rust rust"]);
+        assert!(close(results[0].1, both));
+        assert!(close(results[1].1, one / 2f64));
+    }
+
+    #[test]
+    fn search_variants_rank_like_search() {
+        let docs = ["rust is fast", "rust is fast", "rust and go and more words here", "go is simple", "rust", "python"];
+        let settings = Settings {
+            coordination_factor: true,
+            length_penalty: 0.5,
+            dedup: true,
+            default_limit: Some(3),
+            ..Settings::default()
+        };
+        let mut m = model_with(&docs, settings);
+        m.set_static_rank(vec![0f64, 0f64, 0f64, 0f64, 1f64]);
+        let query = || String::from("rust fast");
+        let expected = m.search(query());
+        assert_eq!(texts(&expected), vec!["rust is fast", "rust", "rust and go and more words here"]);
+
+        let by_index = |ranked: Vec<(usize, f64)>| ranked.into_iter().map(|(i, score)| (docs[i].to_string(), score)).collect::<Vec<_>>();
+        assert_eq!(m.search_timed(query()).0, expected);
+        assert_eq!(m.search_with_index(query()).into_iter().map(|(_, doc, score)| (doc, score)).collect::<Vec<_>>(), expected);
+        assert_eq!(m.search_sorted_by(query(), |_| 0), expected);
+        assert_eq!(m.search_time_decayed(query(), |_| 1f64), expected);
+        assert_eq!(m.search_min_should_match(query(), MinShouldMatch::Count(0)), expected);
+        assert_eq!(m.search_proximity(query(), 1), expected);
+        assert_eq!(m.search_excluding(query(), &HashSet::new()), expected);
+        assert_eq!(m.search_top_k(query(), 10), expected);
+        assert_eq!(by_index(m.ranked_indices(query())), expected);
+        assert_eq!(m.search_sparse_query(&m.query_term_weights(query())), expected);
+        for (doc, score) in expected.iter() {
+            let i = docs.iter().position(|d| d == doc).unwrap();
+            assert!(close(m.score(query(), i).unwrap(), *score));
+        }
+    }
+
+    #[test]
+    fn background_idf_replaces_the_corpus_idf() {
+        let background = [(String::from("rust"), 10u64)].iter().cloned().collect::<HashMap<_, _>>();
+        let m = Model::with_background_idf(strings(&["rust go", "go"]), background, 1000, pipeline(), Settings::default());
+
+        let (rust, go) = (m.column("rust").unwrap(), m.column("go").unwrap());
+        assert!(close(m.idf(rust), 2f64));
+        assert!(close(m.weights_of(0)[rust], 2f64));
+        // go is unknown to the background and keeps the corpus idf of log10(2 / 2).
+        assert_eq!(m.weights_of(0)[go], 0f64);
+    }
+
+    #[test]
+    fn dedup_collapses_identical_documents() {
+        let docs = ["rust is fast", "go is simple", "rust is fast", "python"];
+        assert_eq!(texts(&model(&docs).search(String::from("rust"))), vec!["rust is fast", "rust is fast"]);
+        let m = model_with(&docs, Settings { dedup: true, ..Settings::default() });
+        assert_eq!(texts(&m.search(String::from("rust"))), vec!["rust is fast"]);
+    }
+
+    #[test]
+    fn lazy_weights_search_like_eager_ones() {
+        let docs = ["rust is fast", "go is simple", "rust and go", "python"];
+        let eager = model(&docs);
+        let mut lazy = model_with(&docs, Settings { lazy_weights: true, ..Settings::default() });
+        assert!(lazy.document_weights.is_empty());
+        for query in ["rust", "go simple", "rust go python"].iter() {
+            assert_eq!(lazy.search(query.to_string()), eager.search(query.to_string()));
+        }
+
+        lazy.update_document(3, String::from("python and rust"));
+        let mut updated = model(&docs);
+        updated.update_document(3, String::from("python and rust"));
+        assert_eq!(lazy.search(String::from("rust")), updated.search(String::from("rust")));
+
+        // switching a built model only takes effect once the weights are computed again.
+        let mut switched = model(&docs);
+        switched.vectorizer.settings.lazy_weights = true;
+        assert_eq!(switched.search(String::from("rust")), eager.search(String::from("rust")));
+        switched.calculate_document_weights();
+        assert!(switched.document_weights.is_empty());
+        assert_eq!(switched.search(String::from("rust")), eager.search(String::from("rust")));
+    }
+
+    #[test]
+    fn field_scoped_query_terms_match_only_their_field() {
+        let m = Model::construct_with_pipeline(articles(), pipeline());
+        let titles = |results: Vec<(Article, f64)>| results.iter().map(|(doc, _)| doc.title.clone()).collect::<Vec<_>>();
+        assert_eq!(titles(m.search(String::from("title:rust"))), vec!["rust"]);
+        assert_eq!(titles(m.search(String::from("body:rust"))), vec!["a systems language"]);
+        assert_eq!(m.search(String::from("rust")).len(), 2);
+        assert!(m.search(String::from("title:simple")).is_empty());
+    }
+
+    #[test]
+    fn field_scoped_query_terms_keep_their_place_in_the_query() {
+        let m = Model::construct_with_settings(vec![
+            KeyValueDocument::new(&[("level", "error"), ("msg", "disk full")]),
+            KeyValueDocument::new(&[("level", "info"), ("msg", "cpu high")]),
+        ], pipeline(), Settings { max_query_terms: Some(1), ..Settings::default() });
+        assert_eq!(m.preprocess_query_terms(String::from("cpu level:error disk")), vec!["cpu", "level:error", "disk"]);
+        let results = m.search(String::from("level:error cpu"));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0.pairs[0].1, "error");
+    }
+
+    #[test]
+    fn queries_with_fields_run_the_pipeline_over_the_whole_text() {
+        let joined: Stage = (Regex::new(r"new york").unwrap(), |_: &Captures| String::from("newyork"));
+        let m = Model::construct_with_pipeline(articles(), vec![joined]);
+        assert!(m.column("newyork").is_some());
+        assert_eq!(m.query_counts(String::from("new york"))[m.column("newyork").unwrap()], 1);
+        let counts = m.query_counts(String::from("new york title:go"));
+        assert_eq!(counts[m.column("newyork").unwrap()], 1);
+        assert_eq!(counts[m.column("title:go").unwrap()], 1);
+    }
+
+    #[test]
+    fn update_document_replaces_its_terms() {
+        let mut m = model(&["rust is fast", "go is simple", "python"]);
+        m.update_document(0, String::from("zig is fast"));
+        assert_eq!(texts(&m.search(String::from("zig"))), vec!["zig is fast"]);
+        assert!(m.search(String::from("rust")).is_empty());
+        assert_eq!(m.document_frequency[m.column("rust").unwrap()], 0);
+        assert_eq!(m.document_frequency[m.column("is").unwrap()], 2);
+    }
+
+    #[test]
+    fn thresholded_top_k_matches_the_exhaustive_top_k() {
+        let docs = ["rust is fast", "rust rust go", "go is simple", "python and rust", "zig", "rust go python zig"];
+        let m = model(&docs);
+        for query in ["rust", "rust go", "python zig", "is"].iter() {
+            let query_weight = m.query_vector(query.to_string());
+            let mut exhaustive = m.weight_rows().enumerate()
+                .map(|(i, doc)| (docs[i].to_string(), Model::<String>::sim(&query_weight, doc)))
+                .filter(|(_, score)| *score > 0.1)
+                .collect::<Vec<_>>();
+            exhaustive.sort_by(|f, b| b.1.partial_cmp(&f.1).unwrap());
+            exhaustive.truncate(3);
+            assert_eq!(m.search_top_k_threshold(query.to_string(), 3, 0.1), exhaustive);
+        }
+    }
+
+    #[test]
+    fn case_folding_matches_sharp_s() {
+        let docs = ["die straße", "der weg"];
+        assert!(model(&docs).search(String::from("STRASSE")).is_empty());
+        let m = model_with(&docs, Settings { case_folding: true, ..Settings::default() });
+        assert_eq!(texts(&m.search(String::from("STRASSE"))), vec!["die straße"]);
+        assert_eq!(Model::<String>::case_fold("İstanbul"), "istanbul");
+    }
+
+    #[test]
+    fn document_frequencies_of_known_and_unknown_terms() {
+        let m = model(&["rust go", "rust", "python"]);
+        assert_eq!(m.document_frequencies(&["Rust", "haskell", "go", "python"]), vec![Some(2), None, Some(1), Some(1)]);
+    }
+
+    #[test]
+    fn sanitize_weights_zeroes_nan() {
+        let mut m = model(&["rust is fast", "go is simple", "python"]);
+        let column = m.column("go").unwrap();
+        m.document_weights[1][column] = f64::NAN;
+        m.document_weights[2][0] = f64::INFINITY;
+        m.invalidate_caches();
+
+        assert_eq!(m.sanitize_weights(), 2);
+        assert_eq!(m.document_weights[1][column], 0f64);
+        assert!(m.weight_rows().flatten().all(|weight| weight.is_finite()));
+        assert!(m.search(String::from("rust simple")).iter().all(|(_, score)| score.is_finite()));
+    }
+
+    #[test]
+    fn include_non_matching_keeps_zero_scores_in_every_search() {
+        let docs = ["rust is fast", "go is simple", "rust and go", "python"];
+        let m = model(&docs);
+        assert_eq!(m.search(String::from("rust")).len(), 2);
+        assert_eq!(m.search_top_k(String::from("rust"), 10).len(), 2);
+        assert_eq!(m.ranked_indices(String::from("rust")).len(), 2);
+        assert_eq!(texts(&m.similar_to_text(String::from("rust"), 10)), vec!["rust is fast", "rust and go"]);
+
+        let m = model_with(&docs, Settings { include_non_matching: true, ..Settings::default() });
+        let results = m.search(String::from("rust"));
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[3].1, 0f64);
+        assert_eq!(m.search_top_k(String::from("rust"), 10), results);
+        assert_eq!(m.ranked_indices(String::from("rust")).len(), 4);
+        assert_eq!(m.similar_to_text(String::from("rust"), 10).len(), 4);
+    }
+
+    #[test]
+    fn decay_lets_a_newer_document_win_a_tie() {
+        let m = model(&["2019 rust is fast", "2024 rust is fast", "2020 go"]);
+        let year = |doc: &String| doc[..4].parse::<f64>().unwrap();
+        let results = m.search_time_decayed(String::from("rust fast"), |doc| 1f64 / (1f64 + 2024f64 - year(doc)));
+        assert_eq!(texts(&results), vec!["2024 rust is fast", "2019 rust is fast"]);
+        assert!(results[0].1 > results[1].1);
+    }
+
+    #[test]
+    fn sparse_weights_are_the_nonzero_dense_entries() {
+        let m = model(&["rust is fast", "go is simple", "python rust"]);
+        let sparse = m.sparse_weights(2).unwrap();
+        let dense = m.weights_of(2).iter().enumerate()
+            .filter(|(_, weight)| **weight != 0f64)
+            .map(|(i, weight)| (i, *weight))
+            .collect::<Vec<_>>();
+        assert_eq!(sparse, dense);
+        assert_eq!(sparse.len(), 2);
+        assert_eq!(m.sparse_weights(3), None);
+    }
+
+    #[test]
+    fn collection_frequency_counts_every_occurrence() {
+        let m = model(&["rust rust go", "rust", "python"]);
+        assert_eq!(m.collection_frequency_of("rust"), Some(3));
+        assert_eq!(m.document_frequency[m.column("rust").unwrap()], 2);
+        assert_eq!(m.collection_frequency_of("haskell"), None);
+    }
+
+    #[test]
+    fn query_likelihood_and_cosine_agree_on_the_obvious_document() {
+        let docs = ["cooking pasta with tomato", "rust borrow checker and rust lifetimes", "go channels", "rust on embedded"];
+        let m = model(&docs);
+        let likelihood = m.search_query_likelihood(String::from("rust lifetimes"), 2000f64);
+        let cosine = m.search(String::from("rust lifetimes"));
+        assert_eq!(likelihood[0].0, docs[1]);
+        assert_eq!(cosine[0].0, docs[1]);
+        assert!(likelihood.iter().all(|(_, score)| *score <= 0f64));
+        assert_eq!(likelihood.len(), 2);
+    }
+
+    #[test]
+    fn empty_documents_are_indexed_as_zero_rows() {
+        let mut m = model(&["", "rust is fast", "   ", "go"]);
+        assert!(m.vectorizer.dictionary.iter().all(|term| !term.is_empty()));
+        assert!(m.weights_of(0).iter().all(|weight| *weight == 0f64));
+        assert_eq!(texts(&m.search(String::from("rust"))), vec!["rust is fast"]);
+        assert_eq!(m.score(String::from("rust"), 2), Some(0f64));
+
+        m.insert_docs(strings(&[""]));
+        m.update_index();
+        assert_eq!(m.documents.len(), 5);
+    }
+
+    #[test]
+    fn char_ngrams_match_misspellings() {
+        let docs = ["the quick fox", "a slow turtle"];
+        assert!(model(&docs).search(String::from("quik")).is_empty());
+        let m = model_with(&docs, Settings { char_ngram: Some(3), ..Settings::default() });
+        let results = m.search(String::from("quik"));
+        assert_eq!(texts(&results), vec!["the quick fox"]);
+        assert!(results[0].1 > 0f64);
+    }
+
+    #[test]
+    fn score_matches_the_search_score() {
+        let docs = ["rust is fast", "rust rust go", "go is simple", "python"];
+        let m = model(&docs);
+        for (doc, score) in m.search(String::from("rust go")) {
+            let i = docs.iter().position(|d| *d == doc).unwrap();
+            assert!(close(m.score(String::from("rust go"), i).unwrap(), score));
+        }
+        assert_eq!(m.score(String::from("rust"), 3), Some(0f64));
+        assert_eq!(m.score(String::from("rust"), 4), None);
+    }
+
+    #[test]
+    fn binary_round_trip_preserves_search() {
+        let docs = (0..40).map(|i| format!("doc{} is about rust and topic{}", i, i % 7)).collect::<Vec<_>>();
+        let m = Model::construct_with_settings(docs, pipeline(), stopwords(&["is"]));
+        let mut buffer = vec![];
+        m.save_binary(&mut buffer).unwrap();
+
+        let loaded = Model::<String>::load_binary(&buffer[..], &[space]).unwrap();
+        for query in ["rust", "doc3 topic3", "topic5 about", "is"].iter() {
+            assert_eq!(loaded.search(query.to_string()), m.search(query.to_string()));
+        }
+        assert_eq!(loaded.vectorizer.dictionary, m.vectorizer.dictionary);
+        assert!(buffer.len() < serde_json::to_vec(&m.stored()).unwrap().len());
+
+        let error = Model::<String>::load_binary(&buffer[..], &[]).err().unwrap();
+        assert_eq!(error, ModelError::PipelineMismatch { expected: 1, found: 0 });
+
+        // the idf function is not stored and has to be set again.
+        let mut custom = Model::construct_with_settings(strings(&["rust go", "rust", "zig"]), pipeline(), Settings::default());
+        custom.set_idf_fn(|_, _| 2f64);
+        let mut buffer = vec![];
+        custom.save_binary(&mut buffer).unwrap();
+        let mut loaded = Model::<String>::load_binary(&buffer[..], &[space]).unwrap();
+        assert_ne!(loaded.search(String::from("rust")), custom.search(String::from("rust")));
+        loaded.set_idf_fn(|_, _| 2f64);
+        assert_eq!(loaded.search(String::from("rust")), custom.search(String::from("rust")));
+    }
+
+    #[test]
+    fn synonyms_expand_query_terms() {
+        let docs = ["learning javascript", "learning rust", "python"];
+        assert!(model(&docs).search(String::from("js")).is_empty());
+        let group = ["js", "javascript"].iter().map(|term| term.to_string()).collect::<HashSet<_>>();
+        let m = model_with(&docs, Settings { synonyms: vec![group], synonym_weight: 0.5, ..Settings::default() });
+        assert_eq!(texts(&m.search(String::from("js"))), vec!["learning javascript"]);
+    }
+
+    #[test]
+    fn warm_up_builds_the_norms() {
+        let mut m = model(&["rust is fast", "go is simple", "python", ""]);
+        m.warm_up();
+        let norms = m.document_norms().to_vec();
+        assert_eq!(norms.len(), 4);
+        for (i, norm) in norms.iter().enumerate() {
+            assert!(close(*norm, Model::<String>::euclidean_len(m.weights_of(i))));
+        }
+        assert_eq!(norms[3], 0f64);
+        assert_eq!(m.inverted_index().len(), m.vector_length);
+    }
+
+    #[test]
+    fn hyphen_modes_and_apostrophes() {
+        let docs = ["state-of-the-art search", "don't panic"];
+        let terms = |hyphens: HyphenMode| {
+            let m = model_with(&docs, Settings { hyphens, strip_apostrophes: true, ..Settings::default() });
+            m.sorted_vocabulary().into_iter().cloned().collect::<Vec<_>>()
+        };
+        assert_eq!(terms(HyphenMode::Join), vec!["dont", "panic", "search", "stateoftheart"]);
+        assert_eq!(terms(HyphenMode::Split), vec!["art", "dont", "of", "panic", "search", "state", "the"]);
+        assert_eq!(terms(HyphenMode::Both), vec!["art", "dont", "of", "panic", "search", "state", "stateoftheart", "the"]);
+
+        let m = model_with(&docs, Settings { hyphens: HyphenMode::Join, ..Settings::default() });
+        assert_eq!(texts(&m.search(String::from("State-of-the-art"))), vec!["state-of-the-art search"]);
+    }
+
+    #[test]
+    fn boolean_must_intersection_matches_a_naive_scan() {
+        let docs = (0..300).map(|i| format!("t{} t{} t{} t{}", i % 3, i % 7, i % 11, i % 13 + 20)).collect::<Vec<_>>();
+        let m = Model::construct_with_pipeline(docs.clone(), pipeline());
+        let query = BooleanQuery {
+            must: vec![String::from("t1"), String::from("t4")],
+            should: vec![String::from("t25")],
+            must_not: vec![String::from("t5")],
+        };
+
+        let query_weight = m.query_vector(String::from("t1 t4 t25"));
+        let has = |i: usize, term: &str| m.term_frequencies[i][m.column(term).unwrap()] > 0;
+        let mut naive = (0..docs.len())
+            .filter(|i| has(*i, "t1") && has(*i, "t4") && !has(*i, "t5"))
+            .map(|i| (docs[i].clone(), Model::<String>::sim(&query_weight, m.weights_of(i))))
+            .collect::<Vec<_>>();
+        naive.sort_by(|f, b| b.1.partial_cmp(&f.1).unwrap());
+
+        let results = m.search_boolean(&query);
+        assert!(!results.is_empty());
+        assert_eq!(results.len(), naive.len());
+        for ((doc, score), (expected, naive_score)) in results.iter().zip(naive.iter()) {
+            assert_eq!(doc, expected);
+            assert!(close(*score, *naive_score));
+        }
+    }
+
+    #[test]
+    fn query_term_weights_favour_rare_terms() {
+        let m = model(&["rust is fast", "go is simple", "rust is safe", "python"]);
+        let weights = m.query_term_weights(String::from("rust python haskell"));
+        assert!(weights["python"] > weights["rust"]);
+        assert!(!weights.contains_key("haskell"));
+    }
+
+    #[test]
+    fn binary_query_ignores_repeated_terms() {
+        let docs = ["rust is fast", "go is fast", "python"];
+        let m = model(&docs);
+        assert_ne!(m.query_vector(String::from("rust rust fast")), m.query_vector(String::from("rust fast")));
+        assert_ne!(m.search(String::from("rust rust fast")), m.search(String::from("rust fast")));
+
+        let m = model_with(&docs, Settings { binary_query: true, ..Settings::default() });
+        assert_eq!(m.search(String::from("rust rust fast")), m.search(String::from("rust fast")));
+        assert_eq!(m.query_counts(String::from("rust rust"))[m.column("rust").unwrap()], 1);
+    }
+
+    #[test]
+    fn documents_by_term_weight_rank_the_highest_tf_first() {
+        let m = model(&["zig", "zig zig zig go", "rust go", "zig zig", "python"]);
+        let ranked = m.documents_by_term_weight("zig", 2);
+        assert_eq!(ranked.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![1, 3]);
+        assert!(ranked[0].1 > ranked[1].1);
+        assert!(m.documents_by_term_weight("haskell", 2).is_empty());
+    }
+
+    #[test]
+    fn constant_idf_fn_leaves_scaled_term_frequencies() {
+        let mut m = model(&["rust rust go", "go", "python rust"]);
+        m.set_idf_fn(|_, _| 2f64);
+        for (i, row) in m.term_frequencies.iter().enumerate() {
+            for (column, tf) in row.iter().enumerate() {
+                let expected = if *tf == 0 { 0f64 } else { 2f64 * (1f64 + (*tf as f64).log10()) };
+                assert!(close(m.weights_of(i)[column], expected));
+            }
+        }
+    }
+
+    #[test]
+    fn nan_idf_fn_does_not_panic_searches() {
+        let mut m = model(&["rust rust go", "go", "python rust", "zig"]);
+        m.set_idf_fn(|df, _| if df == 2 { f64::NAN } else { 1f64 });
+        let query = || String::from("rust python zig");
+        assert!(m.search(query()).iter().all(|(_, score)| *score > 0f64));
+        m.search_top_k(query(), 2);
+        m.search_query_likelihood(query(), 10f64);
+        m.similar_to_text(query(), 2);
+        m.rerank(query(), &[0, 1, 2, 3]);
+        m.search_ann(query(), 2, 2);
+        m.suggest_refinement(query());
+        m.documents_by_term_weight("rust", 2);
+        m.distinguishing_terms(&[0], &[1], 2);
+        assert!(m.nearest_by_vector(m.weights_of(0), 2, DistanceMetric::Euclidean).is_err());
+
+        m.vectorizer.settings.include_non_matching = true;
+        let results = m.search(query());
+        assert_eq!(results.len(), 4);
+        assert!(results.last().unwrap().1.is_nan());
+    }
+
+    #[test]
+    fn grouped_results_keep_the_ranking_within_each_group() {
+        let m = model(&["news rust rust", "sport rust", "news rust go go go", "sport go", "news python"]);
+        let groups = m.search_grouped(String::from("rust"), |doc| doc.split(' ').next().unwrap().to_string());
+        assert_eq!(groups.len(), 2);
+        let news = groups["news"].iter().map(|(i, _)| *i).collect::<Vec<_>>();
+        assert_eq!(news, vec![0, 2]);
+        assert_eq!(groups["sport"].iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![1]);
+        assert!(groups["news"][0].1 > groups["news"][1].1);
+    }
+
+    #[test]
+    fn min_should_match_excludes_documents_with_too_few_terms() {
+        let m = model(&["rust go zig", "rust go", "rust go zig python", "java", "c"]);
+        let query = || String::from("rust go zig python");
+        let results = m.search_min_should_match(query(), MinShouldMatch::Count(3));
+        assert_eq!(results.iter().map(|(doc, _)| doc.as_str()).collect::<Vec<_>>(), vec!["rust go zig python", "rust go zig"]);
+
+        let fraction = m.search_min_should_match(query(), MinShouldMatch::Fraction(0.75));
+        assert_eq!(fraction, results);
+        assert_eq!(m.search_min_should_match(query(), MinShouldMatch::Count(0)).len(), m.search(query()).len());
+    }
+
+    #[test]
+    fn ann_recovers_the_exact_top_result() {
+        let words = ["rust", "go", "zig", "java", "python", "ruby", "perl", "lisp", "ocaml", "haskell", "scala", "swift"];
+        let mut state = 7u64;
+        let docs = (0..200).map(|_| {
+            (0..5).map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                words[(state >> 33) as usize % words.len()]
+            }).collect::<Vec<_>>().join(" ")
+        }).collect::<Vec<_>>();
+        let m = model(&docs.iter().map(|doc| doc.as_str()).collect::<Vec<_>>());
+
+        let mut recovered = 0;
+        for query in ["rust zig", "lisp ocaml haskell", "swift", "go java perl", "python ruby"] {
+            let exact = m.search_top_k(query.to_string(), 1);
+            let approximate = m.search_ann(query.to_string(), 1, 2);
+            if approximate.first().map(|result| result.1) == exact.first().map(|result| result.1) { recovered += 1; }
+        }
+        assert!(recovered >= 4);
+    }
+
+    #[test]
+    fn token_sequence_follows_the_token_order() {
+        let mut settings = Settings { store_positions: true, bigram_boost: Some(2f64), ..Settings::default() };
+        let m = model_with(&["rust go rust zig", "go"], settings.clone());
+        let column = |term: &str| m.column(term).unwrap();
+        assert_eq!(m.token_sequence(0), Some(vec![column("rust"), column("go"), column("rust"), column("zig")]));
+        assert_eq!(m.token_sequence(1), Some(vec![column("go")]));
+        assert_eq!(m.token_sequence(2), None);
+
+        let mut m = Model::construct_with_settings(vec![
+            KeyValueDocument::new(&[("level", "error"), ("msg", "disk full")]),
+            KeyValueDocument::new(&[("level", "info"), ("msg", "disk ok")]),
+        ], pipeline(), settings.clone());
+        m.update_document(1, KeyValueDocument::new(&[("msg", "full disk")]));
+        let column = |term: &str| m.column(term).unwrap();
+        assert_eq!(m.token_sequence(0), Some(vec![column("error"), column("disk"), column("full")]));
+        assert_eq!(m.token_sequence(1), Some(vec![column("full"), column("disk")]));
+
+        settings.store_positions = false;
+        assert_eq!(model_with(&["rust go"], settings).token_sequence(0), None);
+    }
+
+    #[test]
+    fn adjacent_query_terms_rank_higher_with_proximity() {
+        let settings = Settings { store_positions: true, ..Settings::default() };
+        let m = model_with(&["rust a b c d e f go", "rust go a b c d e f", "python"], settings);
+        let query = || String::from("rust go");
+        let plain = m.search(query());
+        assert!(close(plain[0].1, plain[1].1));
+
+        let results = m.search_proximity(query(), 1);
+        assert_eq!(results[0].0, "rust go a b c d e f");
+        assert!(close(results[0].1, 2f64 * results[1].1));
+        assert!(close(results[1].1, plain[1].1));
+    }
+
+    #[test]
+    fn refinement_suggests_a_rare_co_occurring_term() {
+        let m = model(&["rust tokio", "rust web", "rust web", "rust web", "go web", "python"]);
+        assert_eq!(m.suggest_refinement(String::from("rust")), Some(String::from("tokio")));
+        assert_eq!(m.suggest_refinement(String::from("python")), None);
+        assert_eq!(m.suggest_refinement(String::from("unknown")), None);
+    }
+
+    #[test]
+    fn max_query_terms_keeps_the_first_terms() {
+        let docs = ["rust", "go", "zig", "java", "python", "c"];
+        let m = model_with(&docs, Settings { max_query_terms: Some(2), ..Settings::default() });
+        let results = m.search(String::from("rust go zig java python"));
+        let mut found = texts(&results);
+        found.sort();
+        assert_eq!(found, vec!["go", "rust"]);
+        assert_eq!(results, model(&docs).search(String::from("rust go")));
+    }
+
+    #[test]
+    fn key_value_fields_are_queryable_by_prefix() {
+        let m = Model::construct_with_settings(vec![
+            KeyValueDocument::new(&[("level", "error"), ("msg", "disk full")]),
+            KeyValueDocument::new(&[("level", "info"), ("msg", "error rate normal")]),
+            KeyValueDocument::new(&[("level", "debug"), ("msg", "tick")]),
+        ], pipeline(), Settings::default());
+
+        let results = m.search(String::from("level:error"));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0.pairs[1].1, "disk full");
+        assert_eq!(m.search(String::from("error")).len(), 2);
+        assert!(m.search(String::from("msg:level")).is_empty());
+    }
+
+    #[test]
+    fn merge_top_k_of_three_shards() {
+        let shards = vec![
+            vec![(0, 0.9), (1, 0.5), (2, 0.1)],
+            vec![(10, 0.8), (11, 0.7)],
+            vec![],
+            vec![(20, 0.95), (21, 0.5), (22, 0.4)],
+        ];
+        assert_eq!(merge_top_k(&shards, 5), vec![(20, 0.95), (0, 0.9), (10, 0.8), (11, 0.7), (1, 0.5)]);
+        assert_eq!(merge_top_k(&shards, 100).len(), 8);
+        assert!(merge_top_k(&shards, 0).is_empty());
+    }
+
+    #[test]
+    fn df_cutoff_drops_terms_common_to_most_documents() {
+        // "the" is in three of the four documents, so it still has some idf without the cutoff.
+        let docs = ["the rust", "the go", "the zig java", "rust rust java"];
+        let m = model_with(&docs, Settings { query_term_df_cutoff: 0.5, ..Settings::default() });
+        let results = m.search(String::from("the rust"));
+        assert_eq!(results, m.search(String::from("rust")));
+        assert_eq!(texts(&results), vec!["the rust", "rust rust java"]);
+        assert!(m.search(String::from("the")).is_empty());
+
+        let uncut = model(&docs).search(String::from("the rust"));
+        assert_eq!(uncut.len(), 4);
+        assert_eq!(uncut[0].0, "the rust");
+    }
+
+    #[test]
+    fn vocabulary_frequencies_cover_the_dictionary_in_order() {
+        let m = model(&["rust go", "rust", "zig rust"]);
+        let frequencies = m.vocabulary_frequencies().map(|(term, df)| (term.as_str(), df)).collect::<Vec<_>>();
+        assert_eq!(frequencies, vec![("rust", 3), ("go", 1), ("zig", 1)]);
+    }
+
+    #[test]
+    fn repetitive_documents_have_lower_entropy() {
+        let m = model(&["spam spam spam spam", "rust go zig java", "rust rust go go"]);
+        assert!(close(m.document_entropy(0).unwrap(), 0f64));
+        assert!(close(m.document_entropy(1).unwrap(), 2f64));
+        assert!(close(m.document_entropy(2).unwrap(), 1f64));
+        assert_eq!(m.document_entropy(3), None);
+    }
+
+    #[test]
+    fn without_idf_weights_are_the_tf_component() {
+        let m = model_with(&["rust rust go", "rust", "zig"], Settings { use_idf: false, ..Settings::default() });
+        for (i, row) in m.term_frequencies.iter().enumerate() {
+            for (column, tf) in row.iter().enumerate() {
+                let expected = if *tf == 0 { 0f64 } else { 1f64 + (*tf as f64).log10() };
+                assert!(close(m.weights_of(i)[column], expected));
+            }
+        }
+        // rust is in every document but one, with idf it would weigh less than go.
+        let query = m.query_vector(String::from("rust go"));
+        assert!(close(query[m.column("rust").unwrap()], query[m.column("go").unwrap()]));
+    }
+
+    #[test]
+    fn query_counts_are_the_raw_term_counts() {
+        let m = model(&["rust go", "zig"]);
+        let counts = m.query_counts(String::from("rust unknown rust zig"));
+        assert_eq!(counts.len(), m.vector_length);
+        assert_eq!(counts[m.column("rust").unwrap()], 2);
+        assert_eq!(counts[m.column("zig").unwrap()], 1);
+        assert_eq!(counts[m.column("go").unwrap()], 0);
+    }
+
+    #[test]
+    fn sparse_sim_equals_the_dense_sim() {
+        let m = model(&["rust go zig", "rust rust", "java python go", "c", ""]);
+        for query in ["rust", "go zig java", "c c python", "unknown"] {
+            let query_weight = m.query_vector(query.to_string());
+            let sparse = Model::<String>::sparse_query(&query_weight);
+            for i in 0..m.documents.len() {
+                assert!(close(m.sparse_sim(&sparse, i), Model::<String>::sim(&query_weight, m.weights_of(i))));
+            }
+        }
+    }
+
+    #[test]
+    fn overlap_of_a_contained_query_is_one() {
+        let m = model(&["rust go zig java python ruby", "rust c"]);
+        assert_eq!(m.overlap_coefficient(String::from("go rust"), 0), Some(1f64));
+        assert_eq!(m.overlap_coefficient(String::from("go rust"), 1), Some(0.5));
+        assert_eq!(m.overlap_coefficient(String::from("go rust"), 2), None);
+    }
+
+    #[test]
+    fn removal_restores_the_corpus_statistics() {
+        let mut m = model(&["rust go", "zig zig"]);
+        let tokens = m.corpus_token_count();
+        let frequencies = m.collection_frequency.clone();
+        assert_eq!(tokens, 4);
+
+        m.insert_docs(strings(&["rust rust java"]));
+        m.update_index();
+        assert_eq!(m.corpus_token_count(), 7);
+        assert_eq!(m.remove_document(2), Some(String::from("rust rust java")));
+        assert_eq!(m.corpus_token_count(), tokens);
+        assert_eq!(m.collection_frequency[..frequencies.len()], frequencies[..]);
+        assert_eq!(m.document_frequencies(&["java"]), vec![Some(0)]);
+    }
+
+    #[test]
+    fn sparse_query_maps_rank_by_the_given_weights() {
+        let m = model(&["rust", "go", "rust go", "zig"]);
+        let query = vec![(String::from("Rust"), 1f64), (String::from("go"), 3f64), (String::from("unknown"), 5f64)]
+            .into_iter().collect::<HashMap<_, _>>();
+        let results = m.search_sparse_query(&query);
+        assert_eq!(texts(&results), vec!["go", "rust go", "rust"]);
+
+        let flipped = vec![(String::from("rust"), 3f64), (String::from("go"), 1f64)].into_iter().collect::<HashMap<_, _>>();
+        assert_eq!(texts(&m.search_sparse_query(&flipped)), vec!["rust", "rust go", "go"]);
+        assert!(m.search_sparse_query(&HashMap::new()).is_empty());
+    }
+
+    // collects the integer fields of every span and event.
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<(String, u64)>>>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::field::Visit for Recorder {
+        fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+            self.0.lock().unwrap().push((field.name().to_string(), value));
+        }
+
+        fn record_debug(&mut self, _: &tracing::field::Field, _: &dyn Debug) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for Recorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool { true }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            span.record(&mut self.clone());
+            tracing::span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            values.record(&mut self.clone());
+        }
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            event.record(&mut self.clone());
+        }
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn search_spans_carry_the_query_term_count() {
+        let m = model(&["rust go", "zig", "java"]);
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || { m.search(String::from("rust go unknown")); });
+
+        let fields = recorder.0.lock().unwrap().clone();
+        assert!(fields.contains(&(String::from("query_terms"), 3)));
+        assert!(fields.contains(&(String::from("scored"), 3)));
+        assert!(fields.contains(&(String::from("results"), 1)));
+    }
+
+    #[test]
+    fn length_penalty_moves_long_documents_down() {
+        let docs = ["rust go rust go rust go", "rust go", "zig"];
+        let plain = model(&docs).search(String::from("rust go"));
+        assert!(close(plain[0].1, plain[1].1));
+
+        let mut m = model_with(&docs, Settings { length_penalty: 0.5, ..Settings::default() });
+        let results = m.search(String::from("rust go"));
+        assert_eq!(results[0].0, "rust go");
+        assert!(close(results[0].1 / results[1].1, 3f64.sqrt()));
+
+        m.vectorizer.settings.length_penalty = 1f64;
+        let results = m.search(String::from("rust go"));
+        assert_eq!(results[0].0, "rust go");
+        assert!(close(results[0].1 / results[1].1, 3f64));
+    }
+
+    #[test]
+    fn similarity_matrix_is_symmetric_with_a_unit_diagonal() {
+        let m = model(&["rust go", "rust zig zig", "java", ""]);
+        let matrix = m.similarity_matrix();
+        assert_eq!(matrix.len(), 4);
+        for (i, row) in matrix.iter().enumerate() {
+            for (j, similarity) in row.iter().enumerate() {
+                assert!(close(*similarity, matrix[j][i]));
+                assert!(close(*similarity, Model::<String>::sim(m.weights_of(i), m.weights_of(j))));
+            }
+        }
+        assert!((0..3).all(|i| close(matrix[i][i], 1f64)));
+        assert!(close(matrix[3][3], 0f64));
+        assert!(matrix[0][1] > 0f64 && close(matrix[0][2], 0f64));
+    }
+
+    #[test]
+    fn a_serialized_vectorizer_maps_another_corpus_into_the_fitted_space() {
+        let corpus = ["rust is fast", "go is simple", "python"];
+        let fitted = Vectorizer::fit(strings(&corpus), pipeline(), Settings::default());
+        let m = model(&corpus);
+        assert_eq!(fitted.dictionary, m.vectorizer.dictionary);
+        assert_eq!(fitted.vector_length(), m.vector_length);
+
+        let bytes = bincode::serialize(&fitted).unwrap();
+        let loaded = bincode::deserialize::<Vectorizer>(&bytes).unwrap().with_captures(&[space]).unwrap();
+        let other = strings(&["fast rust", "java is new", ""]);
+        let vectors = loaded.transform(&other);
+        for (vector, expected) in vectors.iter().zip(m.transform(&other).iter()) {
+            assert_eq!(vector.len(), m.vector_length);
+            assert!(vector.iter().zip(expected).all(|(a, b)| close(*a, *b)));
+        }
+        assert!(vectors[0][m.column("rust").unwrap()] > 0f64);
+        assert!(vectors[2].iter().all(|weight| *weight == 0f64));
+    }
+
+    #[test]
+    fn the_model_vectorizer_follows_the_index() {
+        let mut m = model(&["rust go", "zig"]);
+        assert_eq!(m.vectorizer.idf, (0..m.vector_length).map(|i| m.idf(i)).collect::<Vec<_>>());
+
+        m.insert_docs(strings(&["java rust"]));
+        m.update_index();
+        assert_eq!(m.vectorizer.vector_length(), m.vector_length);
+        assert_eq!(m.vectorizer.column("java"), m.column("java"));
+        assert!(close(m.vectorizer.idf[m.column("java").unwrap()], m.idf(m.column("java").unwrap())));
+
+        m.remove_document(2);
+        assert_eq!(m.vectorizer.idf[m.column("java").unwrap()], 0f64);
+    }
+
+    #[test]
+    fn normalized_scores_span_zero_to_one() {
+        let docs = ["rust rust go", "rust zig java python", "rust c c c c", "go"];
+        let m = model_with(&docs, Settings { normalize_scores: true, ..Settings::default() });
+        let results = m.search(String::from("rust"));
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].1, 1f64);
+        assert_eq!(results.last().unwrap().1, 0f64);
+        assert_eq!(texts(&results), texts(&model(&docs).search(String::from("rust"))));
+    }
+
+    #[test]
+    fn normalize_term_shows_the_indexed_form() {
+        let settings = Settings { stemmer: Some(Model::<String>::light_stem), ..stopwords(&["the"]) };
+        let m = model_with(&["the runner is running"], settings);
+        assert_eq!(m.normalize_term("Running"), Some(String::from("run")));
+        assert_eq!(m.normalize_term("The"), None);
+        assert_eq!(m.normalize_term("!!"), None);
+        assert_eq!(m.normalize_term("new-york"), Some(String::from("new york")));
+    }
+
+    #[test]
+    fn an_arc_model_is_searched_from_several_threads() {
+        let docs = ["rust go", "rust rust zig", "java python", "go zig", "c"];
+        let expected = model(&docs).search(String::from("rust zig"));
+        // the caches are still empty, the threads race to build them.
+        let shared = std::sync::Arc::new(model(&docs));
+
+        let handles = (0..8).map(|_| {
+            let m = std::sync::Arc::clone(&shared);
+            std::thread::spawn(move || (m.search(String::from("rust zig")), m.search_ann(String::from("rust zig"), 2, 2)))
+        }).collect::<Vec<_>>();
+        for handle in handles {
+            let (results, approximate) = handle.join().unwrap();
+            assert_eq!(results, expected);
+            assert!(approximate.len() <= 2);
+        }
+    }
+
+    #[test]
+    fn text_similarity_of_a_text_to_itself_is_one() {
+        let m = model(&["rust go", "rust zig", "java python", "c"]);
+        assert!(close(m.text_similarity(String::from("rust zig zig"), String::from("rust zig zig")), 1f64));
+        assert!(close(m.text_similarity(String::from("rust zig"), String::from("java c")), 0f64));
+        assert!(close(m.text_similarity(String::from("rust unknown"), String::from("rust")), 1f64));
+        assert_eq!(m.text_similarity(String::from("unknown"), String::from("unknown")), 0f64);
+    }
+
+    #[test]
+    fn bigrams_are_indexed_and_boost_matching_queries() {
+        let docs = ["machine learning is fun", "learning a machine", "machine shop", "python"];
+        let boosted = model_with(&docs, Settings { bigram_boost: Some(3f64), ..Settings::default() });
+        assert!(boosted.vectorizer.dictionary.contains("machine"));
+        assert!(boosted.vectorizer.dictionary.contains("machine learning"));
+
+        let results = boosted.search(String::from("machine learning"));
+        assert_eq!(results[0].0, "machine learning is fun");
+        let plain = model_with(&docs, Settings { bigram_boost: Some(1f64), ..Settings::default() });
+        let unboosted = plain.search(String::from("machine learning"));
+        assert!(results[0].1 / results[1].1 > unboosted[0].1 / unboosted[1].1);
+
+        // a field scoped term does not break up the bigram of the words around it.
+        let m = Model::construct_with_settings(vec![
+            Article::new("rust", "machine learning"),
+            Article::new("rust", "learning machine"),
+            Article::new("go", "machine learning"),
+        ], pipeline(), Settings { bigram_boost: Some(3f64), ..Settings::default() });
+        let results = m.search(String::from("machine title:rust learning"));
+        assert_eq!(results[0].0, Article::new("rust", "machine learning"));
+        assert!(results[0].1 > results[1].1);
+    }
+
+    #[test]
+    fn an_empty_model_searches_to_nothing() {
+        let mut m = Model::construct(Vec::<String>::new(), space, Regex::new(r"\W+").unwrap());
+        assert_eq!(m.vector_length, 0);
+        assert!(m.search(String::from("rust")).is_empty());
+        assert!(m.search_top_k(String::from("rust"), 3).is_empty());
+        assert!(m.ranked_indices(String::from("rust")).is_empty());
+        assert!(m.search_ann(String::from("rust"), 3, 2).is_empty());
+        assert!(m.similar_to_text(String::from("rust"), 3).is_empty());
+        assert!(m.search_query_likelihood(String::from("rust"), 10f64).is_empty());
+
+        m.insert_docs(strings(&["rust go", "zig"]));
+        m.update_index();
+        assert_eq!(m.search(String::from("rust")).len(), 1);
+    }
+
+    #[test]
+    fn lnc_ltc_leaves_idf_out_of_the_document_weights() {
+        let m = model_with(&["rust rust go", "go", "zig"], Settings { smart: SmartScheme::LncLtc, ..Settings::default() });
+        let rust = m.column("rust").unwrap();
+        let go = m.column("go").unwrap();
+        assert!(close(m.weights_of(0)[rust], 1f64 + 2f64.log10()));
+        assert!(close(m.weights_of(0)[go], 1f64));
+
+        let query = m.query_vector(String::from("rust go"));
+        assert!(close(query[rust], m.idf(rust)));
+        assert!(close(query[go], m.idf(go)));
+        assert!(query[rust] > query[go]);
+    }
+
+    #[test]
+    fn the_old_ltc_helpers_match_the_default_scheme() {
+        let idf = Model::<String>::calc_idf(2, 8, 10f64);
+        assert!(close(Model::<String>::calc_query_idf(8, 2, 10f64), idf));
+        assert!(close(Model::<String>::calc_tf_idf(10, idf, 10f64), 2f64 * idf));
+        assert!(close(Model::<String>::calc_tf_idf(3, idf, 10f64), SmartScheme::LtcLtc.weight(3f64, idf, 10f64, false)));
+        assert_eq!(Model::<String>::calc_tf_idf(0, idf, 10f64), 0f64);
+        assert!(close(Model::<String>::calc_query_tf(&100, 10f64), 3f64));
+    }
+
+    #[test]
+    fn a_frozen_vocabulary_ignores_new_terms() {
+        let mut m = model(&["rust go", "zig"]);
+        m.vectorizer.settings.frozen_vocabulary = true;
+        let vector_length = m.vector_length;
+        m.insert_docs(strings(&["java rust", "java"]));
+        assert_eq!(m.update_index(), 0);
+        assert_eq!(m.vector_length, vector_length);
+        assert_eq!(m.column("java"), None);
+        assert!(m.search(String::from("java")).is_empty());
+        assert_eq!(texts(&m.search(String::from("rust"))), vec!["java rust", "rust go"]);
+    }
+
+    #[test]
+    fn diagnose_names_each_cause() {
+        let m = model(&["rust go", "rust", "", "zig"]);
+        let query = || String::from("go zig");
+        assert_eq!(m.diagnose(query(), 9), ZeroReason::OutOfRange);
+        assert_eq!(m.diagnose(String::from("unknown"), 0), ZeroReason::NoKnownQueryTerms);
+        assert_eq!(m.diagnose(query(), 2), ZeroReason::EmptyDocument);
+        assert_eq!(m.diagnose(query(), 1), ZeroReason::NoSharedTerms);
+        match m.diagnose(query(), 0) {
+            ZeroReason::Scored(score) => assert!(score > 0f64),
+            reason => panic!("{:?}", reason),
+        }
+
+        // "rust" is in every document, so its idf is 0.
+        let m = model(&["rust go", "rust"]);
+        assert_eq!(m.diagnose(String::from("rust"), 0), ZeroReason::ZeroWeightTerms);
+    }
+
+    #[test]
+    fn targeted_recompute_equals_a_full_recompute() {
+        let mut m = model(&["rust go", "rust zig zig", "java", "go python"]);
+        // builds the inverted index so update_document patches it.
+        m.search(String::from("rust"));
+        m.update_document(1, String::from("go go ocaml"));
+        m.update_document(3, String::from("rust"));
+
+        let targeted = (0..m.documents.len()).map(|i| m.weights_of(i).to_vec()).collect::<Vec<_>>();
+        let results = m.search(String::from("go rust ocaml"));
+        m.calculate_document_weights();
+        for (i, row) in targeted.iter().enumerate() {
+            assert_eq!(row.len(), m.weights_of(i).len());
+            assert!(row.iter().zip(m.weights_of(i)).all(|(a, b)| close(*a, *b)));
+        }
+        assert_eq!(m.search(String::from("go rust ocaml")), results);
+    }
+
+    #[test]
+    fn an_outlier_is_the_least_representative() {
+        let m = model(&["rust go", "rust go zig", "rust zig", "go zig rust", "cobol fortran"]);
+        let scores = m.representativeness();
+        assert_eq!(scores.len(), 5);
+        let lowest = (0..5).min_by(|a, b| ascending(scores[*a], scores[*b])).unwrap();
+        assert_eq!(lowest, 4);
+        assert!(scores[..4].iter().all(|score| *score > scores[4]));
+    }
+
+    #[test]
+    fn excluded_documents_never_appear() {
+        let m = model(&["rust rust", "rust go", "go", "zig"]);
+        let exclude = [0].iter().copied().collect::<HashSet<_>>();
+        let results = m.search_excluding(String::from("rust"), &exclude);
+        assert_eq!(texts(&results), vec!["rust go"]);
+        assert_eq!(m.search(String::from("rust"))[0].0, "rust rust");
+        assert!(m.search_excluding(String::from("rust"), &[0, 1].iter().copied().collect()).is_empty());
+    }
+
+    #[test]
+    fn combined_queries_follow_their_weights() {
+        let m = model(&["rust", "go", "zig", "java"]);
+        let combined = |weight_a, weight_b| m.search_combined(String::from("rust"), weight_a, String::from("go"), weight_b);
+        assert_eq!(texts(&combined(2f64, 1f64)), vec!["rust", "go"]);
+        assert_eq!(texts(&combined(1f64, 2f64)), vec!["go", "rust"]);
+        let results = combined(3f64, 1f64);
+        assert!(close(results[0].1 / results[1].1, 3f64));
+        assert_eq!(texts(&combined(1f64, 0f64)), vec!["rust"]);
+    }
+
+    #[test]
+    fn distinguishing_terms_come_from_the_first_set() {
+        let m = model(&["rust cargo borrow", "rust cargo", "python pip", "python pip venv", "code"]);
+        let terms = m.distinguishing_terms(&[0, 1], &[2, 3], 2).into_iter().map(|(term, _)| term).collect::<HashSet<_>>();
+        assert_eq!(terms, ["rust", "cargo"].iter().map(|term| term.to_string()).collect());
+        let reversed = m.distinguishing_terms(&[2, 3], &[0, 1], 1);
+        assert!(reversed[0].0 == "python" || reversed[0].0 == "pip");
+        assert!(reversed[0].1 > 0f64);
+    }
+
+    #[test]
+    fn default_limit_caps_search_results() {
+        let docs = (0..20).map(|i| format!("rust doc{}", i)).chain(std::iter::once(String::from("go"))).collect::<Vec<_>>();
+        let docs = docs.iter().map(|doc| doc.as_str()).collect::<Vec<_>>();
+        let m = model_with(&docs, Settings { default_limit: Some(5), ..Settings::default() });
+        assert_eq!(m.search(String::from("rust")).len(), 5);
+        assert_eq!(m.ranked_indices(String::from("rust")).len(), 5);
+        assert_eq!(m.search(String::from("go")).len(), 1);
+        assert_eq!(model(&docs).search(String::from("rust")).len(), 20);
+    }
+
+    #[test]
+    fn libsvm_lines_hold_the_sparse_weights() {
+        let m = model(&["rust rust go", "zig", "go java"]);
+        let output = m.to_libsvm(&[1, -1]);
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+
+        let mut fields = lines[0].split(' ');
+        assert_eq!(fields.next(), Some("1"));
+        let parsed = fields.map(|field| {
+            let (column, weight) = field.split_once(':').unwrap();
+            (column.parse::<usize>().unwrap() - 1, weight.parse::<f64>().unwrap())
+        }).collect::<Vec<_>>();
+        assert_eq!(parsed, m.sparse_weights(0).unwrap());
+        assert!(lines[1].starts_with("-1 "));
+    }
+
+    fn padded(_: &Captures) -> String {
+        String::from(" \t ")
+    }
+
+    #[test]
+    fn captures_leaving_whitespace_give_clean_terms() {
+        let stage: Stage = (Regex::new(r"[,;]").unwrap(), padded);
+        let m = Model::construct_with_pipeline(strings(&["rust,go;rust", "go , zig\n"]), vec![stage]);
+        assert_eq!(m.vectorizer.dictionary.iter().map(|term| term.as_str()).collect::<Vec<_>>(), vec!["rust", "go", "zig"]);
+        assert_eq!(m.search(String::from("zig"))[0].0, "go , zig\n");
+    }
+
+    #[test]
+    fn min_idf_drops_common_query_terms() {
+        let m = model(&["the the the go", "the rust java python", "the zig", "rust"]);
+        let query = || String::from("the rust");
+        assert_eq!(m.search(query()).len(), 4);
+
+        let results = m.search_min_idf(query(), 0.2);
+        assert_eq!(results, m.search(String::from("rust")));
+        assert_eq!(texts(&results), vec!["rust", "the rust java python"]);
+        assert!(m.search_min_idf(query(), 10f64).is_empty());
+    }
+
+    #[test]
+    fn update_index_counts_the_new_terms() {
+        let mut m = model(&["rust go", "zig"]);
+        m.insert_docs(strings(&["rust java", "java ocaml go", "zig"]));
+        assert_eq!(m.update_index(), 2);
+        assert_eq!(m.update_index(), 0);
+
+        let mut hashed = model_with(&["rust go"], Settings { hashing_features: Some(64), ..Settings::default() });
+        hashed.insert_docs(strings(&["java"]));
+        assert_eq!(hashed.update_index(), 0);
+    }
+
+    #[test]
+    fn static_rank_breaks_a_relevance_tie() {
+        let mut m = model(&["rust go", "go rust", "zig"]);
+        let tied = m.search(String::from("rust"));
+        assert!(close(tied[0].1, tied[1].1));
+        assert_eq!(tied[0].0, "rust go");
+
+        m.set_static_rank(vec![1f64, 4f64]);
+        let results = m.search(String::from("rust"));
+        assert_eq!(results[0].0, "go rust");
+        assert!(close(results[0].1, 2f64 * tied[0].1));
+        assert!(close(results[1].1, 1.25 * tied[0].1));
+    }
+
+    #[test]
+    fn specific_queries_are_clearer_than_common_ones() {
+        let m = model(&["the rust", "the go", "the zig ocaml", "the java", "a rust"]);
+        let specific = m.query_difficulty(String::from("zig ocaml"));
+        let common = m.query_difficulty(String::from("the rust"));
+        assert!(specific > common);
+        assert!(close(specific, 5f64.log10()));
+        assert_eq!(m.query_difficulty(String::from("unknown")), 0f64);
+    }
+
+    #[test]
+    fn nearest_by_vector_rejects_non_finite_entries() {
+        let m = model(&["rust go", "zig", "java"]);
+        let mut query = m.weights_of(0).to_vec();
+        query[1] = f64::NAN;
+        assert_eq!(m.nearest_by_vector(&query, 2, DistanceMetric::Cosine).err(), Some(ModelError::NonFinite { index: 1 }));
+        query[1] = f64::INFINITY;
+        let error = m.nearest_by_vector(&query, 2, DistanceMetric::Euclidean).err().unwrap();
+        assert_eq!(error.to_string(), "vector entry 1 is not a finite number");
+    }
+
+    #[test]
+    fn euclidean_and_cosine_can_disagree() {
+        let m = model(&["rust", "rust rust rust rust rust rust rust rust", "zig", "go"]);
+        let rust = m.column("rust").unwrap();
+        let mut query = vec![0f64; m.vector_length];
+        query[rust] = m.weights_of(0)[rust];
+        query[m.column("zig").unwrap()] = 0.1;
+
+        let cosine = m.nearest_by_vector(&query, 2, DistanceMetric::Cosine).unwrap();
+        assert!(close(cosine[0].1, cosine[1].1));
+        let euclidean = m.nearest_by_vector(&query, 1, DistanceMetric::Euclidean).unwrap();
+        assert_eq!(euclidean[0].0, 0);
+        let dot = m.nearest_by_vector(&query, 1, DistanceMetric::DotProduct).unwrap();
+        assert_eq!(dot[0].0, 1);
+    }
+
+    #[test]
+    fn progress_ends_at_the_total() {
+        let docs = (0..250).map(|i| format!("rust doc{}", i)).collect::<Vec<_>>();
+        let mut calls = vec![];
+        let m = Model::construct_with_progress(docs, pipeline(), Settings::default(), |processed, total| calls.push((processed, total)));
+        assert_eq!(m.documents.len(), 250);
+        assert_eq!(calls.last(), Some(&(250, 250)));
+        assert!(calls.len() > 2);
+        assert!(calls.windows(2).all(|pair| pair[0].0 < pair[1].0 && pair[1].1 == 250));
+
+        let mut calls = vec![];
+        Model::<String>::construct_with_progress(vec![], pipeline(), Settings::default(), |processed, total| calls.push((processed, total)));
+        assert_eq!(calls, vec![(0, 0)]);
+    }
+
+    #[test]
+    fn unique_terms_are_the_ones_no_other_document_has() {
+        let m = model(&["rust go tokio", "rust go", "go"]);
+        assert_eq!(m.unique_terms(0), vec![String::from("tokio")]);
+        assert!(m.unique_terms(1).is_empty());
+        assert!(m.unique_terms(3).is_empty());
+    }
+
+    #[test]
+    fn searches_after_a_mutation_see_the_change() {
+        let mut m = model(&["rust go", "zig", "java"]);
+        let search = |m: &Model<String>| texts(&m.search(String::from("rust ocaml"))).iter().map(|doc| doc.to_string()).collect::<Vec<_>>();
+        // search and warm_up fill the norms and inverted index, search_ann the lsh tables.
+        assert_eq!(search(&m), vec!["rust go"]);
+        m.search_ann(String::from("rust"), 1, 1);
+        m.warm_up();
+
+        m.insert_docs(strings(&["ocaml rust"]));
+        m.update_index();
+        assert_eq!(search(&m), vec!["ocaml rust", "rust go"]);
+        assert_eq!(m.search_ann(String::from("ocaml"), 1, 8)[0].0, "ocaml rust");
+
+        m.update_document(1, String::from("rust rust ocaml"));
+        assert_eq!(search(&m).len(), 3);
+        let mut matching = m.search_with_index(String::from("ocaml")).iter().map(|(i, _, _)| *i).collect::<Vec<_>>();
+        matching.sort_unstable();
+        assert_eq!(matching, vec![1, 3]);
+
+        m.remove_document(0);
+        assert_eq!(search(&m), vec!["ocaml rust", "rust rust ocaml"]);
+
+        m.insert_counted([(String::from("ocaml"), 3)].iter().cloned().collect(), String::from("counted"));
+        assert!(search(&m).contains(&String::from("counted")));
+        assert_eq!(m.search(String::from("ocaml"))[0].0, "counted");
+    }
+
+    fn halve_go(term: &str) -> f64 {
+        if term == "go" { 0.5 } else { 1f64 }
+    }
+
+    #[test]
+    fn pos_weight_scales_a_terms_contribution() {
+        let docs = ["rust go", "rust", "go zig", "java"];
+        let plain = model(&docs);
+        let weighted = model_with(&docs, Settings { pos_weight: Some(halve_go), ..Settings::default() });
+        let go = plain.column("go").unwrap();
+        let rust = plain.column("rust").unwrap();
+        assert!(close(weighted.weights_of(0)[go], 0.5 * plain.weights_of(0)[go]));
+        assert!(close(weighted.weights_of(0)[rust], plain.weights_of(0)[rust]));
+        assert!(weighted.search(String::from("go"))[0].1 <= plain.search(String::from("go"))[0].1);
+        assert!(weighted.score(String::from("rust go"), 0) < plain.score(String::from("rust go"), 0));
+
+        // set on a built model, the rows and transformed texts agree.
+        let mut m = model(&docs);
+        m.set_pos_weight(Some(halve_go));
+        for (i, row) in m.transform(&strings(&docs)).iter().enumerate() {
+            assert!(row.iter().zip(m.weights_of(i)).all(|(a, b)| close(*a, *b)));
+            assert!(row.iter().zip(weighted.weights_of(i)).all(|(a, b)| close(*a, *b)));
+        }
+        m.set_pos_weight(None);
+        assert!(close(m.weights_of(0)[go], plain.weights_of(0)[go]));
+    }
+}